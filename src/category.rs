@@ -10,7 +10,10 @@ use crate::{
 pub struct Category {
     pub name: String,
     pub tags: Vec<String>,
+    /// The excluded tags of the category merged with the global excluded tags
+    pub exclude_tags: Vec<String>,
     pub aspect_ratios: Vec<String>,
+    pub effects: Vec<String>,
 }
 
 impl Category {
@@ -18,9 +21,16 @@ impl Category {
         Self {
             name: config.name,
             tags: config.tags,
+            exclude_tags: CONFIG
+                .exclude_tags
+                .iter()
+                .cloned()
+                .chain(config.exclude_tags)
+                .collect(),
             aspect_ratios: config
                 .aspect_ratios
                 .unwrap_or(CONFIG.aspect_ratios.to_owned()),
+            effects: config.effects,
         }
     }
 
@@ -2,9 +2,19 @@ use std::process::ExitCode;
 
 use clap::Parser;
 
+use crate::hooks;
+
+mod back;
+mod cache;
 mod collections;
+mod daemon;
 mod fetch;
 mod get;
+mod history;
+mod next;
+mod palette;
+mod pause;
+mod prev;
 mod set;
 
 #[derive(Parser)]
@@ -27,6 +37,25 @@ enum Commands {
     /// Seach order: Url, Path, Collection, Category, Tag
     #[clap(visible_alias("use"))]
     Set(set::SetArgs),
+    /// Rotate the wallpaper on the schedule from the config
+    Daemon(daemon::DaemonArgs),
+    /// Make the daemon change to a new wallpaper
+    Next(next::NextArgs),
+    /// Go back to the previous wallpaper, through the daemon if it runs
+    Prev(prev::PrevArgs),
+    /// Go back a number of wallpapers in the history
+    Back(back::BackArgs),
+    /// List the previously set wallpapers
+    History(history::HistoryArgs),
+    /// Pause or resume the rotation of the daemon
+    Pause(pause::PauseArgs),
+    /// Print the colour palette of the current wallpaper
+    Palette(palette::PaletteArgs),
+    /// Inspect and clean up the downloaded images
+    Cache {
+        #[command(subcommand)]
+        commands: cache::CacheCommands,
+    },
 }
 
 pub struct Program;
@@ -40,12 +69,21 @@ impl Program {
             Commands::Collections { commands } => commands.run(),
             Commands::Get(args) => args.run(),
             Commands::Set(args) => args.run(),
+            Commands::Daemon(args) => args.run(),
+            Commands::Next(args) => args.run(),
+            Commands::Prev(args) => args.run(),
+            Commands::Back(args) => args.run(),
+            Commands::History(args) => args.run(),
+            Commands::Pause(args) => args.run(),
+            Commands::Palette(args) => args.run(),
+            Commands::Cache { commands } => commands.run(),
         };
 
         match result {
             Ok(_) => ExitCode::SUCCESS,
             Err(err) => {
                 println!("Command failed: '{:?}'", err);
+                hooks::report_error(&err.to_string());
                 ExitCode::FAILURE
             }
         }
@@ -0,0 +1,19 @@
+use clap::{builder::RangedU64ValueParser, Args};
+
+use crate::state::State;
+
+#[derive(Args, Debug, Clone)]
+pub struct BackArgs {
+    /// The amount of wallpapers to go back.
+    #[arg(default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    steps: usize,
+}
+
+impl BackArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let entry = State::open()?.go_back(self.steps)?;
+        println!("Set wallpaper to image: {}", entry.image.get_image_path());
+
+        Ok(())
+    }
+}
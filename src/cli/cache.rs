@@ -0,0 +1,48 @@
+mod clean;
+mod clear;
+mod list;
+mod pin;
+mod promote;
+mod stats;
+
+use crate::{image::SavedImage, state::State, IMAGECACHE};
+
+/// The cached image by its file name, or the current wallpaper of the output for 'current'
+fn find_image(name: &str, output: Option<&str>) -> anyhow::Result<SavedImage> {
+    match name {
+        "current" => Ok(State::open()?.get_current_image(output)?),
+        name => Ok(IMAGECACHE.find(name)?),
+    }
+}
+
+#[derive(Clone, clap::Subcommand)]
+pub enum CacheCommands {
+    /// Show how much the cache holds next to its limits
+    Stats(stats::StatsArgs),
+    /// Remove images past the limits of the config, the least recently used first
+    Clean(clean::CleanArgs),
+    /// Remove every cached image except pinned images and the current wallpapers
+    Clear(clear::ClearArgs),
+    /// List the cached images, the least recently used first
+    List(list::ListArgs),
+    /// Keep a cached image through every cleanup
+    Pin(pin::PinArgs),
+    /// Let a pinned image be cleaned up again
+    Unpin(pin::UnpinArgs),
+    /// Move a cached image into a collection, keeping where it came from
+    Promote(promote::PromoteArgs),
+}
+
+impl CacheCommands {
+    pub fn run(self) -> anyhow::Result<()> {
+        match self {
+            CacheCommands::Stats(args) => args.run(),
+            CacheCommands::Clean(args) => args.run(),
+            CacheCommands::Clear(args) => args.run(),
+            CacheCommands::List(args) => args.run(),
+            CacheCommands::Pin(args) => args.run(),
+            CacheCommands::Unpin(args) => args.run(),
+            CacheCommands::Promote(args) => args.run(),
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use clap::Args;
+use indicatif::HumanBytes;
+
+use crate::{state::State, IMAGECACHE};
+
+#[derive(Clone, Args)]
+pub struct ClearArgs {}
+
+impl ClearArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let summary = IMAGECACHE.clear(&State::open()?.get_displayed_paths())?;
+
+        println!(
+            "Removed {} images, freeing {}",
+            summary.removed,
+            HumanBytes(summary.freed)
+        );
+
+        Ok(())
+    }
+}
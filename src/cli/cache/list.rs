@@ -0,0 +1,23 @@
+use clap::Args;
+use indicatif::HumanBytes;
+
+use crate::IMAGECACHE;
+
+#[derive(Clone, Args)]
+pub struct ListArgs {}
+
+impl ListArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        for file in IMAGECACHE.get_files()? {
+            println!(
+                "{} {:>10} {}{}",
+                file.get_last_used_time(),
+                HumanBytes(file.size).to_string(),
+                file.path.to_string_lossy(),
+                if file.pinned { " (pinned)" } else { "" }
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,45 @@
+use clap::Args;
+
+use crate::IMAGECACHE;
+
+use super::find_image;
+
+#[derive(Clone, Args)]
+pub struct PinArgs {
+    /// The file name of the cached image, or 'current' for the current wallpaper
+    image: String,
+    /// The output to take the current wallpaper of, leave empty for the wallpaper of all outputs
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+impl PinArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let image = find_image(&self.image, self.output.as_deref())?;
+        IMAGECACHE.set_pinned(&image, true)?;
+
+        println!("Pinned: {}", image.get_path().to_string_lossy());
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Args)]
+pub struct UnpinArgs {
+    /// The file name of the cached image, or 'current' for the current wallpaper
+    image: String,
+    /// The output to take the current wallpaper of, leave empty for the wallpaper of all outputs
+    #[arg(short, long)]
+    output: Option<String>,
+}
+
+impl UnpinArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let image = find_image(&self.image, self.output.as_deref())?;
+        IMAGECACHE.set_pinned(&image, false)?;
+
+        println!("Unpinned: {}", image.get_path().to_string_lossy());
+
+        Ok(())
+    }
+}
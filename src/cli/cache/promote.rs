@@ -0,0 +1,68 @@
+use clap::Args;
+
+use crate::{
+    collections::{manifest::ImageMetadata, Collection, DuplicatePolicy, ImportMode},
+    state::{ImageStateType, State},
+    IMAGECACHE,
+};
+
+use super::find_image;
+
+#[derive(Clone, Args)]
+pub struct PromoteArgs {
+    /// The collection to move the image into
+    collection: String,
+    /// The file name of the cached image, or 'current' for the current wallpaper
+    #[arg(short, long, default_value = "current")]
+    image: String,
+    /// The output to take the current wallpaper of, leave empty for the wallpaper of all outputs
+    #[arg(short, long)]
+    output: Option<String>,
+    /// What to do when the image is already in the collection, or its name is taken
+    #[arg(long, value_enum, default_value_t)]
+    on_duplicate: DuplicatePolicy,
+}
+
+impl PromoteArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let mut collection = Collection::open(&self.collection)?;
+
+        let image = find_image(&self.image, self.output.as_deref())?;
+        let image_path = image.get_absolute_path_as_string()?;
+        let entry = IMAGECACHE.get_entry(&image)?;
+
+        let metadata = ImageMetadata {
+            source_url: entry.source_url,
+            supplier: entry.supplier,
+            original_id: entry.remote_id,
+            tags: entry.tags,
+            ..Default::default()
+        };
+
+        let promoted = collection.get_directory_mut().import_image(
+            &image,
+            metadata,
+            ImportMode::Move,
+            self.on_duplicate,
+        )?;
+        IMAGECACHE.forget(&image)?;
+
+        // The wallpaper stays the same, it is now shown from the collection
+        let mut state = State::open()?;
+        state.relocate_image(
+            &image_path,
+            &ImageStateType::Collection {
+                name: collection.get_name().to_owned(),
+                image_path: promoted.get_absolute_path_as_string()?,
+            },
+        );
+
+        println!(
+            "Moved {} into collection: {}",
+            promoted.get_name().unwrap_or_default(),
+            self.collection
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use clap::Args;
+use indicatif::HumanBytes;
+
+use crate::{image::cache::DEFAULT_MAX_AGE, CONFIG, IMAGECACHE};
+
+#[derive(Clone, Args)]
+pub struct StatsArgs {}
+
+impl StatsArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let files = IMAGECACHE.get_files()?;
+        let size: u64 = files.iter().map(|file| file.size).sum();
+        let config = &CONFIG.cache;
+
+        let limit = |limit: Option<String>| match limit {
+            Some(limit) => format!(" (max {})", limit),
+            None => String::new(),
+        };
+
+        println!("Location: {}", IMAGECACHE.get_path().to_string_lossy());
+        println!(
+            "Images: {}{}",
+            files.len(),
+            limit(config.max_count.map(|max_count| max_count.to_string()))
+        );
+        println!(
+            "Size: {}{}",
+            HumanBytes(size),
+            limit(
+                config
+                    .max_size
+                    .map(|max_size| HumanBytes(max_size * 1024 * 1024).to_string())
+            )
+        );
+
+        println!(
+            "Pinned: {}",
+            files.iter().filter(|file| file.pinned).count()
+        );
+
+        println!(
+            "Kept for: {} days after their last use",
+            config.max_age.unwrap_or(DEFAULT_MAX_AGE)
+        );
+
+        if let (Some(oldest), Some(newest)) = (files.first(), files.last()) {
+            println!("Least recently used: {}", oldest.get_last_used_time());
+            println!("Most recently used: {}", newest.get_last_used_time());
+        }
+
+        Ok(())
+    }
+}
@@ -1,8 +1,11 @@
 mod create;
+mod dedupe;
 mod delete;
 mod from;
 mod list;
+mod rate;
 mod save;
+mod similar;
 mod sync;
 
 #[derive(Clone, clap::Subcommand)]
@@ -13,6 +16,12 @@ pub enum CollectionCommands {
     From(from::FromArgs),
     Sync(sync::SyncArgs),
     List(list::ListArgs),
+    /// Rate an image, used by the rating selection strategy
+    Rate(rate::RateArgs),
+    /// Find identical images within the collection and in other collections
+    Dedupe(dedupe::DedupeArgs),
+    /// Find images in the collection that look nearly the same
+    Similar(similar::SimilarArgs),
 }
 
 impl CollectionCommands {
@@ -24,6 +33,9 @@ impl CollectionCommands {
             CollectionCommands::From(args) => args.run(),
             CollectionCommands::Sync(args) => args.run(),
             CollectionCommands::List(args) => args.run(),
+            CollectionCommands::Rate(args) => args.run(),
+            CollectionCommands::Dedupe(args) => args.run(),
+            CollectionCommands::Similar(args) => args.run(),
         }
     }
 }
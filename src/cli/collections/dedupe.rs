@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use clap::Args;
+
+use crate::{
+    collections::{manifest::get_file_name, Collection},
+    image::SavedImage,
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct DedupeArgs {
+    /// The name of the collection.
+    collection: String,
+    /// Remove the duplicates within the collection, keeping one of each image.
+    #[arg(long, short)]
+    remove: bool,
+}
+
+impl DedupeArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let mut collection = Collection::open(&self.collection)?;
+
+        // Images of the collection grouped by their content
+        let mut groups: BTreeMap<String, Vec<SavedImage>> = BTreeMap::new();
+        for (image, content_hash) in collection.get_directory_mut().get_image_hashes()? {
+            groups.entry(content_hash).or_default().push(image);
+        }
+
+        // Identical images in other collections, as 'collection/file name'
+        let mut elsewhere: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for name in Collection::list()? {
+            if name == self.collection {
+                continue;
+            }
+
+            let mut other = Collection::open(&name)?;
+            for (image, content_hash) in other.get_directory_mut().get_image_hashes()? {
+                if groups.contains_key(&content_hash) {
+                    elsewhere.entry(content_hash).or_default().push(format!(
+                        "{}/{}",
+                        name,
+                        get_file_name(&image)
+                    ));
+                }
+            }
+        }
+
+        let mut found = 0;
+        let mut removed = 0;
+
+        for (content_hash, images) in groups {
+            let others = elsewhere.remove(&content_hash).unwrap_or_default();
+            if images.len() < 2 && others.is_empty() {
+                continue;
+            }
+            found += 1;
+
+            println!("Identical images:");
+            for image in &images {
+                println!("  {}/{}", self.collection, get_file_name(image));
+            }
+            for other in others {
+                println!("  {}", other);
+            }
+
+            if self.remove {
+                for image in images.iter().skip(1) {
+                    collection.get_directory_mut().remove_image(image)?;
+                    removed += 1;
+                }
+            }
+        }
+
+        if found == 0 {
+            println!(
+                "No duplicates found for the collection: {}",
+                self.collection
+            );
+        } else if self.remove {
+            println!(
+                "Removed {} duplicate images from the collection: {}",
+                removed, self.collection
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -1,56 +1,185 @@
-use std::path::{Path, PathBuf};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{anyhow, bail};
 use clap::Args;
+use git2::Repository;
+use glob::Pattern;
 
-use crate::collections::Collection;
+use crate::{
+    collections::{manifest::ImageMetadata, Collection, DuplicatePolicy, ImportMode},
+    image::SavedImage,
+};
 
 #[derive(Debug, Clone, Args)]
 pub struct FromArgs {
     /// Overwrite the name of the collection if set.
     #[arg(long, short)]
     output_name: Option<String>,
-    /// The url of the repository, use SSH for private repositories.
+    /// Also import images from subdirectories when importing a directory.
+    #[arg(long, short)]
+    recursive: bool,
+    /// Only import files matching one of these glob patterns, e.g. '*.png'.
+    #[arg(long, short)]
+    include: Vec<Pattern>,
+    /// Skip files matching one of these glob patterns.
+    #[arg(long, short)]
+    exclude: Vec<Pattern>,
+    /// How to bring the images of a directory into the collection.
+    #[arg(long, short, value_enum, default_value_t)]
+    mode: ImportMode,
+    /// What to do when an image is already in the collection, or its name is taken.
+    #[arg(long, value_enum, default_value_t)]
+    on_duplicate: DuplicatePolicy,
+    /// The url of the repository, use SSH for private repositories, or a local directory.
     url: String,
 }
 
 impl FromArgs {
-    pub fn run(self) -> anyhow::Result<()> {
-        let url_path = PathBuf::from(&self.url);
+    fn find_files(directory: &Path, recursive: bool) -> anyhow::Result<Vec<PathBuf>> {
+        let mut files = vec![];
 
-        match url_path.extension() {
-            Some(extension) => {
-                let ext = extension
-                    .to_str()
-                    .ok_or(anyhow!("Extension not a string"))?
-                    .to_ascii_lowercase();
-                match ext.as_str() {
-                    "git" => {
-                        // TODO: Add a per collection config for naming, tags etc etc
-                        match self.output_name {
-                            Some(name) => {
-                                Collection::clone(&self.url, &name)?;
-                            }
-                            None => {
-                                let path = Path::new(&self.url);
-                                let stem = match path.file_stem() {
-                                    Some(stem) => stem,
-                                    None => bail!("No file stem definied"),
-                                }
-                                .to_string_lossy();
-
-                                Collection::clone(&self.url, &stem)?;
-                            }
-                        };
-
-                        Ok(())
+        for entry in fs::read_dir(directory)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            // Symlinked directories are not followed, as they can loop back
+            if entry.file_type()?.is_dir() {
+                if recursive {
+                    files.extend(Self::find_files(&path, recursive)?);
+                }
+            } else if path.is_file() {
+                files.push(path);
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn is_included(&self, relative_path: &Path) -> bool {
+        let included = self.include.is_empty()
+            || self
+                .include
+                .iter()
+                .any(|pattern| pattern.matches_path(relative_path));
+        let excluded = self
+            .exclude
+            .iter()
+            .any(|pattern| pattern.matches_path(relative_path));
+
+        included && !excluded
+    }
+
+    fn import_directory(&self, directory: &Path) -> anyhow::Result<()> {
+        // Collection names are lowercase without spaces, so a directory like 'My Walls' becomes 'my-walls'
+        let name = match &self.output_name {
+            Some(name) => name.clone(),
+            None => fs::canonicalize(directory)?
+                .file_name()
+                .ok_or(anyhow!("The directory has no name, use --output-name"))?
+                .to_string_lossy()
+                .to_lowercase()
+                .replace(' ', "-"),
+        };
+
+        let mut collection = Collection::open(&name)?;
+
+        let mut files = Self::find_files(directory, self.recursive)?;
+        files.sort();
+
+        let mut imported = 0;
+        let mut skipped = vec![];
+
+        for file in files {
+            let relative_path = file.strip_prefix(directory).unwrap_or(&file);
+            if !self.is_included(relative_path) {
+                continue;
+            }
+
+            let result = SavedImage::from_path(&file)
+                .map_err(anyhow::Error::from)
+                .and_then(|image| {
+                    // Make sure the file actually is an image before importing it, only reading its header.
+                    // The image is decoded once while importing it.
+                    let reader = image::ImageReader::new(io::BufReader::new(fs::File::open(
+                        image.get_path(),
+                    )?))
+                    .with_guessed_format()?;
+                    if reader.format().is_none() {
+                        bail!("Not a supported image");
                     }
-                    _ => bail!("Fetching from this url is not possible"),
+                    collection.get_directory_mut().import_image(
+                        &image,
+                        ImageMetadata::default(),
+                        self.mode,
+                        self.on_duplicate,
+                    )?;
+
+                    Ok(())
+                });
+
+            match result {
+                Ok(_) => imported += 1,
+                Err(err) => skipped.push((file, err)),
+            }
+        }
+
+        println!("Imported {} images into the collection: {}", imported, name);
+        if !skipped.is_empty() {
+            println!("Skipped {} files:", skipped.len());
+            for (file, err) in skipped {
+                println!("  {}: {}", file.display(), err);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn clone_repository(&self) -> anyhow::Result<()> {
+        match &self.output_name {
+            Some(name) => {
+                Collection::clone(&self.url, name)?;
+            }
+            None => {
+                let path = Path::new(&self.url);
+                let stem = match path.file_stem() {
+                    Some(stem) => stem,
+                    None => bail!("No file stem definied"),
                 }
+                .to_string_lossy();
+
+                Collection::clone(&self.url, &stem)?;
             }
-            None => todo!("Implement directory importing"),
+        };
+
+        Ok(())
+    }
+
+    pub fn run(self) -> anyhow::Result<()> {
+        let url_path = PathBuf::from(&self.url);
+
+        let has_git_extension = match url_path.extension() {
+            Some(extension) => extension
+                .to_str()
+                .ok_or(anyhow!("Extension not a string"))?
+                .eq_ignore_ascii_case("git"),
+            None => false,
+        };
+
+        // Local repositories, bare or not, are cloned like remote ones
+        if has_git_extension || Repository::open(&url_path).is_ok() {
+            return self.clone_repository();
+        }
+
+        if url_path.is_dir() {
+            return self.import_directory(&url_path);
         }
 
-        // TODO: Add a nice output
+        match url_path.extension() {
+            Some(_) => bail!("Fetching from this url is not possible"),
+            None => bail!("The directory: {} does not exist", self.url),
+        }
     }
 }
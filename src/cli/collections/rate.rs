@@ -0,0 +1,29 @@
+use clap::Args;
+
+use crate::collections::Collection;
+
+#[derive(Debug, Clone, Args)]
+pub struct RateArgs {
+    /// The name of the collection.
+    collection: String,
+    /// The name of the image in the collection.
+    image: String,
+    /// The rating, 0 keeps the image from being picked by rating.
+    #[arg(value_parser = clap::value_parser!(u8).range(0..=5))]
+    rating: u8,
+}
+
+impl RateArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let mut collection = Collection::open(&self.collection)?;
+        let directory = collection.get_directory_mut();
+        let image = directory.find_image(&self.image)?;
+
+        directory.get_manifest_mut().get_image_mut(&image).rating = Some(self.rating);
+        directory.save_manifest()?;
+
+        println!("Rated image: {} with: {}", self.image, self.rating);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,12 @@
-use crate::{collections::Collection, image::ExternalImage, state::State};
+use std::str::FromStr;
+
+use reqwest::Url;
+
+use crate::{
+    collections::{manifest::ImageMetadata, Collection, DuplicatePolicy},
+    image::ExternalImage,
+    state::State,
+};
 
 #[derive(clap::Args, Clone, Debug)]
 pub struct SaveImageArgs {
@@ -6,20 +14,32 @@ pub struct SaveImageArgs {
     collection: String,
     /// Can be a image path or url, or leave empty to query the current wallpaper
     which: Option<String>,
+    /// What to do when the image is already in the collection, or its name is taken
+    #[arg(long, value_enum, default_value_t)]
+    on_duplicate: DuplicatePolicy,
 }
 
 impl SaveImageArgs {
     pub fn run(self) -> anyhow::Result<()> {
-        let collection = Collection::open(&self.collection)?;
+        let mut collection = Collection::open(&self.collection)?;
 
         let state = State::open()?;
 
-        let image = match self.which {
-            Some(path) => ExternalImage::new(&path).load()?,
-            None => state.get_current_image()?,
+        let image = match &self.which {
+            Some(path) => ExternalImage::new(path).load(&state.get_displayed_paths())?,
+            None => state.get_current_image(None)?,
+        };
+
+        let metadata = ImageMetadata {
+            source_url: self
+                .which
+                .filter(|which| Url::from_str(which).is_ok_and(|url| url.has_host())),
+            ..Default::default()
         };
 
-        collection.get_directory().add_image(&image)?;
+        collection
+            .get_directory_mut()
+            .add_image(&image, metadata, self.on_duplicate)?;
 
         println!("Added current wallpaper to collection: {}", self.collection);
 
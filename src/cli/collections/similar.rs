@@ -0,0 +1,57 @@
+use clap::Args;
+
+use crate::{
+    collections::{manifest::get_file_name, Collection},
+    image::similarity::DEFAULT_SIMILARITY_THRESHOLD,
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct SimilarArgs {
+    /// The name of the collection.
+    collection: String,
+    /// The amount of differing bits, from 0 to 64, up to which images count as similar.
+    #[arg(long, short, default_value_t = DEFAULT_SIMILARITY_THRESHOLD)]
+    threshold: u32,
+}
+
+impl SimilarArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let mut collection = Collection::open(&self.collection)?;
+        let mut remaining = collection.get_directory_mut().get_perceptual_hashes()?;
+
+        let mut found = 0;
+
+        while !remaining.is_empty() {
+            let (image, perceptual_hash) = remaining.remove(0);
+
+            let (similar, rest) = remaining.into_iter().partition::<Vec<_>, _>(|(_, other)| {
+                perceptual_hash.is_similar(other, self.threshold)
+            });
+            remaining = rest;
+
+            if similar.is_empty() {
+                continue;
+            }
+            found += 1;
+
+            println!("Similar images:");
+            println!("  {}", get_file_name(&image));
+            for (other, other_hash) in similar {
+                println!(
+                    "  {} (distance: {})",
+                    get_file_name(&other),
+                    perceptual_hash.distance(&other_hash)
+                );
+            }
+        }
+
+        if found == 0 {
+            println!(
+                "No similar images found in the collection: {}",
+                self.collection
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -28,7 +28,7 @@ impl SyncArgs {
             let remote_url: String = dialoguer::Input::new()
                 .with_prompt("Please specify the remote repository url")
                 .validate_with(|value: &String| -> Result<(), &str> {
-                    if check_regex.is_match(&value) {
+                    if check_regex.is_match(value) {
                         Ok(())
                     } else {
                         Err("Not a valid git repository")
@@ -0,0 +1,16 @@
+use clap::Args;
+
+use crate::daemon::Daemon;
+
+#[derive(Args, Debug, Clone)]
+pub struct DaemonArgs {}
+
+impl DaemonArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let daemon = Daemon::from_config()?;
+        println!("Started the wallpaper daemon");
+        daemon.run()?;
+
+        Ok(())
+    }
+}
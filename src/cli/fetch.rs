@@ -1,17 +1,21 @@
-use std::{fs, path::PathBuf, time::Duration};
+use std::{collections::HashSet, fs, path::PathBuf, time::Duration};
 
 use anyhow::{anyhow, bail};
 use clap::Args;
-use indicatif::ProgressBar;
-use rand::seq::SliceRandom;
+use futures_util::{stream, StreamExt};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 
 use crate::{
     category::Category,
-    config::GlobalConfig,
-    finder::{check_string_equality, find_best_by_value},
-    image::{FetchedImage, SearchParameters, UrlSupplier},
+    collections::{manifest::ImageMetadata, Collection, CollectionError, DuplicatePolicy},
+    hooks::{HookContext, HookEvent},
+    image::{
+        effects::Effects,
+        similarity::{PerceptualHash, DEFAULT_SIMILARITY_THRESHOLD},
+        FetchedImage, ImageError, ImageUrl, SearchParameters, UrlSupplier,
+    },
     state::State,
-    CONFIG, IMAGECACHE,
+    HTTPCLIENT, IMAGECACHE,
 };
 
 #[derive(Args, Clone, Debug)]
@@ -26,128 +30,401 @@ pub struct FetchArgs {
     /// Which predefined category name to use.
     category: Option<String>,
     #[arg(short, long)]
-    /// Which supplier to use, leave empty to pick randomly.
-    supplier: Option<String>,
+    /// Which suppliers to search at the same time, leave empty to pick randomly.
+    supplier: Vec<String>,
     #[arg(short, long)]
     // Additional tags to add.
     tags: Vec<String>,
+    #[arg(short = 'x', long)]
+    /// Additional tags to exclude.
+    exclude: Vec<String>,
     #[arg(long)]
     /// Only return the images final path, for use in scripts.
     simple: bool,
+    #[arg(long)]
+    /// Skip images that look like an image already in this collection.
+    skip_similar: Option<String>,
+    #[arg(long, default_value_t = DEFAULT_SIMILARITY_THRESHOLD, requires = "skip_similar")]
+    /// The amount of differing bits, from 0 to 64, up to which images count as similar.
+    threshold: u32,
+    #[arg(long, conflicts_with_all = ["output", "assign"])]
+    /// Save the images straight into this collection.
+    into: Option<String>,
+    #[arg(short = 'n', long, default_value_t = 1, requires = "into")]
+    /// How many distinct images to download at the same time into the collection.
+    count: usize,
+    #[arg(long, requires = "into")]
+    /// Commit the new images to the git repository of the collection.
+    commit: bool,
 }
 
+/// How many similar images are skipped before giving up
+const MAX_SIMILAR_SKIPS: usize = 10;
+/// A finished download with the search result and supplier it came from
+type Download = (Result<FetchedImage, ImageError>, ImageUrl, String);
+
+/// How many images are downloaded at the same time
+const MAX_PARALLEL_DOWNLOADS: usize = 4;
+/// How many searches are done before giving up on finding new images
+const MAX_SEARCHES: usize = 3;
+
 impl FetchArgs {
-    pub fn run(self) -> anyhow::Result<()> {
-        let category = {
-            match self.category {
-                Some(category_name) => Some(Category::find_in_config(&category_name)?),
-                None => None,
+    fn download(&self, search_result: ImageUrl) -> anyhow::Result<FetchedImage> {
+        if self.simple {
+            Ok(HTTPCLIENT.block_on(FetchedImage::fetch_from_url(search_result))?)
+        } else {
+            let pb = ProgressBar::new_spinner();
+            pb.enable_steady_tick(Duration::from_millis(120));
+            pb.set_message("Downloading...");
+            let style = ProgressStyle::with_template(
+                "{msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec})",
+            )?
+            .progress_chars("=> ");
+
+            let image = HTTPCLIENT.block_on(FetchedImage::fetch_from_url_with_progress(
+                search_result,
+                |downloaded, total| {
+                    // Stays a spinner if the server doesn't send the size
+                    if let Some(total) = total {
+                        if pb.length() != Some(total) {
+                            pb.set_style(style.clone());
+                            pb.set_length(total);
+                        }
+                        pb.set_position(downloaded);
+                    }
+                },
+            ))?;
+            pb.finish_with_message("Downloaded");
+
+            Ok(image)
+        }
+    }
+
+    /// Downloads the next search result, skipping images similar to the 'skip_similar' collection.
+    /// Returns the image with the url it was downloaded from and the name of its supplier.
+    fn fetch(
+        &self,
+        url_suppliers: &[UrlSupplier],
+        parameters: &SearchParameters,
+        state: &mut State,
+    ) -> anyhow::Result<(FetchedImage, String, String)> {
+        let search_next = |state: &mut State| -> anyhow::Result<(ImageUrl, String)> {
+            let (search_result, url_supplier) =
+                HTTPCLIENT.block_on(UrlSupplier::search_next(url_suppliers, parameters, state))?;
+
+            Ok((search_result, url_supplier.get_name().to_owned()))
+        };
+
+        let collection_name = match &self.skip_similar {
+            Some(collection_name) => collection_name,
+            None => {
+                let (search_result, supplier) = search_next(state)?;
+                let source_url = search_result.get_url().to_string();
+
+                return Ok((self.download(search_result)?, source_url, supplier));
             }
         };
 
-        let parameters = {
-            match category {
-                Some(category) => SearchParameters {
-                    tags: self
-                        .tags
-                        .into_iter()
-                        .chain(category.tags.into_iter())
-                        .collect(),
-                    aspect_ratios: category.aspect_ratios,
-                    skip_cache: true,
-                },
-                // TODO: Add aspect ratio arg in cli
-                None => SearchParameters {
-                    tags: self.tags,
-                    aspect_ratios: CONFIG.aspect_ratios.clone(),
-                    skip_cache: true,
-                },
+        let known_hashes = self.get_known_hashes()?;
+
+        for _ in 0..MAX_SIMILAR_SKIPS {
+            let (search_result, supplier) = search_next(state)?;
+            let source_url = search_result.get_url().to_string();
+            let image = self.download(search_result)?;
+
+            if !self.is_similar(&image, &known_hashes) {
+                return Ok((image, source_url, supplier));
+            }
+
+            if !self.simple {
+                println!(
+                    "Skipped an image similar to one in the collection: {}",
+                    collection_name
+                );
             }
+        }
+
+        bail!(
+            "Only found images similar to the collection: {}",
+            collection_name
+        )
+    }
+
+    /// The hashes of the images in the 'skip_similar' collection, empty if it is not set
+    fn get_known_hashes(&self) -> anyhow::Result<Vec<PerceptualHash>> {
+        let Some(collection_name) = &self.skip_similar else {
+            return Ok(vec![]);
         };
 
-        // TODO: Move this to a function.
-        let url_supplier: UrlSupplier = {
-            if CONFIG.suppliers.len() == 0 {
-                bail!("No suppliers defined in config file.");
+        Ok(Collection::open(collection_name)?
+            .get_directory_mut()
+            .get_perceptual_hashes()?
+            .into_iter()
+            .map(|(_, perceptual_hash)| perceptual_hash)
+            .collect())
+    }
+
+    fn is_similar(&self, image: &FetchedImage, known_hashes: &[PerceptualHash]) -> bool {
+        image.get_perceptual_hash().is_ok_and(|perceptual_hash| {
+            known_hashes
+                .iter()
+                .any(|known| perceptual_hash.is_similar(known, self.threshold))
+        })
+    }
+
+    /// Searches until there are 'count' results which are not in the collection yet, or the searches run out.
+    /// Each search continues where the previous one stopped, so results skipped as known aren't found again.
+    /// Returns the results with the name of their supplier.
+    fn search_new(
+        &self,
+        collection: &Collection,
+        url_suppliers: &[UrlSupplier],
+        parameters: &SearchParameters,
+        state: &mut State,
+    ) -> anyhow::Result<Vec<(ImageUrl, String)>> {
+        let known_ids: HashSet<(String, String)> = collection
+            .get_directory()
+            .get_manifest()
+            .images
+            .values()
+            .filter_map(|metadata| {
+                Some((metadata.supplier.clone()?, metadata.original_id.clone()?))
+            })
+            .collect();
+
+        let mut search_results: Vec<(ImageUrl, String)> = vec![];
+        for _ in 0..MAX_SEARCHES {
+            if search_results.len() >= self.count {
+                break;
             }
 
-            let supplier_file = match self.supplier {
-                Some(supplier_name) => {
-                    let (equal, best_value) = find_best_by_value(
-                        supplier_name.as_str(),
-                        CONFIG.suppliers.iter(),
-                        |value| value.name.as_str(),
-                        |v1, v2| check_string_equality(v1, v2),
-                    );
+            // Every search takes the results it needs from a page, instead of fetching the page for each result
+            let (page_results, url_supplier) =
+                HTTPCLIENT.block_on(UrlSupplier::search_next_many(
+                    url_suppliers,
+                    parameters,
+                    state,
+                    self.count - search_results.len(),
+                ))?;
+            let supplier = url_supplier.get_name().to_owned();
 
-                    if let Some(value) = best_value {
-                        if equal {
-                            value
-                        } else {
-                            bail!(
-                                "No category for name: {}, did you mean: {}?",
-                                supplier_name,
-                                value.name
-                            );
-                        }
-                    } else {
-                        bail!("No suppliers for name: {}", supplier_name);
-                    }
+            for search_result in page_results {
+                let is_known = search_result.get_remote_id().is_some_and(|remote_id| {
+                    known_ids.contains(&(supplier.clone(), remote_id.to_owned()))
+                }) || search_results
+                    .iter()
+                    .any(|(known, _)| known.get_url() == search_result.get_url());
+
+                if !is_known && search_results.len() < self.count {
+                    search_results.push((search_result, supplier.clone()));
                 }
-                None => {
-                    // Unwrap here, seeing that there being no entry in the array is checked earlier.
-                    CONFIG.suppliers.choose(&mut rand::thread_rng()).unwrap()
+            }
+        }
+
+        Ok(search_results)
+    }
+
+    /// Downloads the search results in parallel, showing the bytes of every download
+    fn download_all(
+        &self,
+        search_results: Vec<(ImageUrl, String)>,
+    ) -> anyhow::Result<Vec<Download>> {
+        let progress = if self.simple {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        } else {
+            MultiProgress::new()
+        };
+        let style = ProgressStyle::with_template("{msg:24!} [{bar:30}] {bytes}/{total_bytes}")?
+            .progress_chars("=> ");
+
+        let downloads = search_results.into_iter().map(|(image_url, supplier)| {
+            let bar = progress.add(
+                ProgressBar::new(0)
+                    .with_style(style.clone())
+                    .with_message(image_url.get_stem().to_owned()),
+            );
+
+            async move {
+                let result = FetchedImage::fetch_from_url_with_progress(
+                    image_url.clone(),
+                    |downloaded, total| {
+                        // Without a known size the bar stays full
+                        bar.set_length(total.unwrap_or(downloaded));
+                        bar.set_position(downloaded);
+                    },
+                )
+                .await;
+
+                match &result {
+                    Ok(_) => bar.finish(),
+                    Err(_) => bar.abandon_with_message(format!("{} failed", image_url.get_stem())),
                 }
-            };
 
-            let file_path = GlobalConfig::get_config_path().join(&supplier_file.file);
-            let file = std::fs::read_to_string(&file_path);
+                (result, image_url, supplier)
+            }
+        });
+
+        Ok(HTTPCLIENT.block_on(
+            stream::iter(downloads)
+                .buffer_unordered(MAX_PARALLEL_DOWNLOADS)
+                .collect(),
+        ))
+    }
 
-            match file {
-                Ok(file_content) => toml::from_str(&file_content)?,
+    /// Downloads 'count' new images into the collection, skipping images it already has
+    fn run_batch(
+        &self,
+        collection_name: &str,
+        url_suppliers: &[UrlSupplier],
+        parameters: &SearchParameters,
+        state: &mut State,
+    ) -> anyhow::Result<()> {
+        let mut collection = Collection::open(collection_name)?;
+        let known_hashes = self.get_known_hashes()?;
+
+        // Images in cache are fine, only images already in the collection are skipped
+        let parameters = SearchParameters {
+            skip_cache: false,
+            ..parameters.clone()
+        };
+        let search_results = self.search_new(&collection, url_suppliers, &parameters, state)?;
+        if search_results.len() < self.count && !self.simple {
+            println!("Only found {} new images", search_results.len());
+        }
+
+        let mut added = 0;
+        for (result, image_url, supplier) in self.download_all(search_results)? {
+            let image = match result {
+                Ok(image) => image,
+                // Printed to stderr, so the paths printed with --simple stay the only output
                 Err(err) => {
-                    bail!(
-                        "Failed to read supplier file: {:?}, reason: {} ",
-                        file_path,
+                    eprintln!(
+                        "Failed to download: {}, reason: {}",
+                        image_url.get_url(),
                         err
                     );
+                    continue;
                 }
+            };
+
+            if self.is_similar(&image, &known_hashes) {
+                if !self.simple {
+                    println!(
+                        "Skipped an image similar to one in the collection: {}",
+                        self.skip_similar.as_deref().unwrap_or_default()
+                    );
+                }
+                continue;
             }
-        };
-        let search_result = url_supplier.search(parameters)?;
 
-        let image = if self.simple {
-            FetchedImage::fetch_from_url(search_result)?
-        } else {
-            let pb = ProgressBar::new_spinner();
-            pb.enable_steady_tick(Duration::from_millis(120));
-            pb.set_message("Downloading...");
-            let image = FetchedImage::fetch_from_url(search_result)?;
-            pb.finish_with_message("Downloaded");
+            let metadata = ImageMetadata {
+                source_url: Some(image_url.get_url().to_string()),
+                supplier: Some(supplier.clone()),
+                original_id: image_url.get_remote_id().map(str::to_owned),
+                tags: image_url.get_tags().to_vec(),
+                ..Default::default()
+            };
+            let saved_image = match collection.get_directory_mut().add_image(
+                &IMAGECACHE.cache(&image, &state.get_displayed_paths())?,
+                metadata,
+                DuplicatePolicy::Skip,
+            ) {
+                Ok(saved_image) => saved_image,
+                Err(err @ (CollectionError::DuplicateImage(_) | CollectionError::NameTaken(_))) => {
+                    if !self.simple {
+                        println!("Skipped: {}", err);
+                    }
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
 
-            image
+            HookContext {
+                image: Some(saved_image.get_absolute_path_as_string()?),
+                collection: Some(collection_name.to_owned()),
+                supplier: Some(supplier),
+                source_url: Some(image_url.get_url().to_string()),
+                ..Default::default()
+            }
+            .run_reporting(HookEvent::PostFetch);
+
+            if self.simple {
+                println!("{}", saved_image.get_absolute_path_as_string()?);
+            }
+            added += 1;
+        }
+
+        if !self.simple {
+            println!("Added {} images to collection: {}", added, collection_name);
+        }
+
+        if self.commit && added > 0 {
+            match collection.get_repository() {
+                Some(repository) => {
+                    repository.commit_all(&format!("Add {} fetched images", added))?
+                }
+                None => bail!(
+                    "The collection: {} has no git repository to commit to",
+                    collection_name
+                ),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn run(self) -> anyhow::Result<()> {
+        let category = {
+            match &self.category {
+                Some(category_name) => Some(Category::find_in_config(category_name)?),
+                None => None,
+            }
         };
 
-        let saved_image = if let Some(output_file) = self.output {
+        // The effects are applied when the image is assigned, they are checked before fetching
+        let effects = category
+            .as_ref()
+            .map(|category| category.effects.clone())
+            .unwrap_or_default();
+        Effects::from_list(&effects)?;
+
+        let parameters = SearchParameters::new(self.tags.clone(), self.exclude.clone(), category);
+        let url_suppliers = UrlSupplier::find_many_in_config(&self.supplier)?;
+
+        let mut state = State::open()?;
+        if let Some(collection_name) = &self.into {
+            return self.run_batch(collection_name, &url_suppliers, &parameters, &mut state);
+        }
+
+        let (image, source_url, supplier) = self.fetch(&url_suppliers, &parameters, &mut state)?;
+
+        let saved_image = if let Some(output_file) = &self.output {
             let pb = ProgressBar::new_spinner();
             pb.enable_steady_tick(Duration::from_millis(120));
             pb.set_message("Saving image to file...");
-            let saved_image = image.save_to_format(&output_file)?;
+            let saved_image = image.save_to_format(output_file)?;
             pb.finish_with_message(format!(
                 "Successfully saved image to file: {}",
-                fs::canonicalize(&output_file)?
+                fs::canonicalize(output_file)?
                     .to_str()
                     .ok_or(anyhow!("Failed to convert image path to string."))?
             ));
 
             saved_image
         } else {
-            IMAGECACHE.cache(&image)?
+            IMAGECACHE.cache(&image, &state.get_displayed_paths())?
         };
 
+        HookContext {
+            image: Some(saved_image.get_absolute_path_as_string()?),
+            supplier: Some(supplier),
+            source_url: Some(source_url),
+            ..Default::default()
+        }
+        .run_reporting(HookEvent::PostFetch);
+
         if self.assign {
-            let mut state = State::open()?;
-            state.set_current_image(&saved_image)?;
+            state.set_current_image_with_effects(&saved_image, effects, None)?;
             let result = state.assign_current_image();
 
             match result {
@@ -1,14 +1,27 @@
 use clap::Args;
 
-use crate::state::State;
+use crate::{image::effects::Effects, state::State};
 
 #[derive(Args, Debug, Clone)]
-pub struct GetArgs {}
+pub struct GetArgs {
+    #[arg(short, long)]
+    /// The output (monitor) to get the wallpaper of, leave empty for the wallpaper of all outputs.
+    output: Option<String>,
+    #[arg(short, long)]
+    /// Get a copy of the wallpaper with effects, like 'blur=12,dim=0.4,grayscale,tint=#88aaff'.
+    effect: Option<Effects>,
+}
 
 impl GetArgs {
     pub fn run(self) -> anyhow::Result<()> {
         let state = State::open()?;
-        let image_path = state.get_current_image()?.get_absolute_path()?;
+        let image = state.get_current_image(self.output.as_deref())?;
+        let image = match &self.effect {
+            Some(effects) => effects.apply_to(&image)?,
+            None => image,
+        };
+
+        let image_path = image.get_absolute_path()?;
         println!("{}", image_path.to_string_lossy());
         Ok(())
     }
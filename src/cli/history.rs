@@ -0,0 +1,37 @@
+use clap::Args;
+
+use crate::history::History;
+
+#[derive(Args, Debug, Clone)]
+pub struct HistoryArgs {}
+
+impl HistoryArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let history = History::open()?;
+
+        for (index, entry) in history.get_entries().iter().enumerate() {
+            let current = if index == history.get_position() {
+                "*"
+            } else {
+                " "
+            };
+            let output = match &entry.output {
+                Some(output) => format!(" [{}]", output),
+                None => String::new(),
+            };
+            let missing = if entry.is_missing() { " (missing)" } else { "" };
+
+            println!(
+                "{}{:>3} {}{} {}{}",
+                current,
+                index,
+                entry.get_time(),
+                output,
+                entry.image.get_image_path(),
+                missing
+            );
+        }
+
+        Ok(())
+    }
+}
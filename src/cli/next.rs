@@ -0,0 +1,15 @@
+use clap::Args;
+
+use crate::daemon::{send_command, DaemonCommand};
+
+#[derive(Args, Debug, Clone)]
+pub struct NextArgs {}
+
+impl NextArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let message = send_command(DaemonCommand::Next)?;
+        println!("{}", message);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,30 @@
+use clap::Args;
+
+use crate::{
+    image::palette::{Palette, PaletteFormat, DEFAULT_PALETTE_SIZE},
+    state::State,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct PaletteArgs {
+    #[arg(short, long, value_enum, default_value_t)]
+    /// How to write out the colours.
+    format: PaletteFormat,
+    #[arg(short, long)]
+    /// The output (monitor) to get the palette of, leave empty for the wallpaper of all outputs.
+    output: Option<String>,
+    #[arg(short, long, default_value_t = DEFAULT_PALETTE_SIZE)]
+    /// The amount of colours to extract.
+    size: usize,
+}
+
+impl PaletteArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let state = State::open()?;
+        let image = state.get_current_image(self.output.as_deref())?;
+        let palette = Palette::from_image(&image, self.size)?;
+
+        println!("{}", palette.format(self.format));
+        Ok(())
+    }
+}
@@ -0,0 +1,15 @@
+use clap::Args;
+
+use crate::daemon::{send_command, DaemonCommand};
+
+#[derive(Args, Debug, Clone)]
+pub struct PauseArgs {}
+
+impl PauseArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let message = send_command(DaemonCommand::Pause)?;
+        println!("{}", message);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+use clap::Args;
+
+use crate::{
+    daemon::{send_command, DaemonCommand, DaemonError},
+    state::State,
+};
+
+#[derive(Args, Debug, Clone)]
+pub struct PrevArgs {}
+
+impl PrevArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        // Go through the daemon if it runs, so it resets its timer
+        let message = match send_command(DaemonCommand::Prev) {
+            Ok(message) => message,
+            Err(DaemonError::NotRunning) => {
+                let entry = State::open()?.go_back(1)?;
+                format!("Set wallpaper to image: {}", entry.image.get_image_path())
+            }
+            Err(err) => return Err(err.into()),
+        };
+        println!("{}", message);
+
+        Ok(())
+    }
+}
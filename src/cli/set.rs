@@ -2,11 +2,11 @@ use std::{path::PathBuf, str::FromStr};
 
 use anyhow::bail;
 use clap::Args;
-use reqwest::Url;
 
 use crate::{
-    collections::Collection,
-    image::{ExternalImage, FetchedImage, ImageUrl, SavedImage},
+    collections::{filter::ImageFilter, usage::SelectionStrategy, Collection},
+    history::History,
+    image::{ExternalImage, SavedImage},
     state::{ImageStateType, State},
 };
 
@@ -18,24 +18,47 @@ enum FetchImageResultData {
 #[derive(Args, Debug, Clone)]
 pub struct SetArgs {
     #[arg(short, long)]
-    /// Reapply the last wallpaper set.
+    /// Reapply the last wallpaper set, for every output.
     reapply: bool,
+    #[arg(short, long)]
+    /// The output (monitor) to set the wallpaper for, leave empty for all outputs.
+    output: Option<String>,
+    #[arg(long, conflicts_with = "name")]
+    /// Set the wallpaper at this index of 'walltz history'.
+    from_history: Option<usize>,
+    #[arg(long)]
+    /// How to pick the image when setting from a collection, overrides the configured strategy.
+    strategy: Option<SelectionStrategy>,
+    #[arg(long)]
+    /// Pick from every image of a collection, ignoring the configured aspect ratios and minimum resolution.
+    any_shape: bool,
     /// Which name to search for.
     name: Option<String>,
 }
 
 impl SetArgs {
-    fn fetch_image(name: &str) -> anyhow::Result<FetchImageResultData> {
-        let external_image = ExternalImage::new(name.to_owned()).load();
+    fn get_filter(&self) -> Option<ImageFilter> {
+        self.any_shape.then(ImageFilter::none)
+    }
+
+    fn fetch_image(
+        name: &str,
+        strategy: Option<SelectionStrategy>,
+        filter: Option<&ImageFilter>,
+        keep: &[PathBuf],
+    ) -> anyhow::Result<FetchImageResultData> {
+        let external_image = ExternalImage::new(name.to_owned()).load(keep);
 
         if let Ok(image) = external_image {
             return Ok(FetchImageResultData::Image(image));
         }
 
-        let collection = Collection::open(&name);
+        let collection = Collection::open(name);
 
-        if let Ok(collection) = collection {
-            let image = collection.get_directory().get_random_image()?;
+        if let Ok(mut collection) = collection {
+            let image = collection
+                .get_directory_mut()
+                .get_random_image(strategy, filter)?;
 
             return Ok(FetchImageResultData::Collection(collection, image));
         }
@@ -49,47 +72,78 @@ impl SetArgs {
         let mut state = State::open()?;
 
         if self.reapply {
-            let image_state = state.get_state();
-            if let Some(image_state) = image_state {
+            let image_states = state
+                .get_states()
+                .map(|(output, image_state)| (output.map(str::to_owned), image_state.clone()))
+                .collect::<Vec<_>>();
+
+            if image_states.is_empty() {
+                bail!("No state set to reapply");
+            }
+
+            // The new states are picked first, so replacing one doesn't affect the others
+            let mut new_states = vec![];
+            for (output, image_state) in image_states {
                 match image_state {
-                    ImageStateType::Image { path } => {
+                    ImageStateType::Image { path, .. } => {
                         if !PathBuf::from_str(&path).is_ok_and(|v| v.is_file()) {
                             bail!("Cannot reapply, targeted file no longer exists");
                         }
-                        state.assign_current_image()?;
                     }
                     ImageStateType::Collection {
                         name,
                         image_path: _,
                     } => {
-                        let colletion = Collection::open(&name)?;
-                        state.set_current_collection(
-                            &colletion,
-                            &colletion.get_directory().get_random_image()?,
-                        )?;
-                        state.assign_current_image()?;
+                        let mut colletion = Collection::open(&name)?;
+                        let image = colletion
+                            .get_directory_mut()
+                            .get_random_image(self.strategy, self.get_filter().as_ref())?;
+                        let image_path = image.get_absolute_path_as_string()?;
+                        new_states.push((output, ImageStateType::Collection { name, image_path }));
                     }
                 }
-            } else {
-                bail!("No state set to reapply");
             }
+
+            for (output, image_state) in new_states {
+                state.replace_state(image_state, output.as_deref())?;
+            }
+            state.assign_current_image()?;
+
             return Ok(());
         }
 
-        if let Some(name) = self.name {
-            let image = Self::fetch_image(&name)?;
+        if let Some(index) = self.from_history {
+            // The history is written by setting the state, so it is closed before that
+            let entry = History::open()?.get_entry(index)?.clone();
+            let output = self.output.as_deref().or(entry.output.as_deref());
+
+            state.set_state(entry.image.clone(), output)?;
+            state.assign_output(output)?;
+
+            println!("Set wallpaper to image: {}", entry.image.get_image_path());
+
+            return Ok(());
+        }
+
+        if let Some(name) = &self.name {
+            let image = Self::fetch_image(
+                name,
+                self.strategy,
+                self.get_filter().as_ref(),
+                &state.get_displayed_paths(),
+            )?;
 
             let image_path = match image {
                 FetchImageResultData::Image(image) => {
-                    state.set_current_image(&image)?;
+                    state.set_current_image(&image, self.output.as_deref())?;
                     image.get_absolute_path()?
                 }
                 FetchImageResultData::Collection(collection, image) => {
-                    state.set_current_collection(&collection, &image)?;
+                    state.set_current_collection(&collection, &image, self.output.as_deref())?;
                     image.get_absolute_path()?
                 }
             };
-            state.assign_current_image()?;
+            state.assign_output(self.output.as_deref())?;
 
             println!("Set wallpaper to image: {:?}", image_path);
         } else {
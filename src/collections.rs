@@ -5,13 +5,21 @@ use std::{
     vec,
 };
 
+pub mod filter;
+pub mod manifest;
+pub mod usage;
+
 use git2::{build::RepoBuilder, Cred, FetchOptions, PushOptions, RemoteCallbacks, Repository};
 use image::ImageFormat;
-use rand::seq::IteratorRandom;
-use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{image::SavedImage, BASEDIRECTORIES, CONFIG};
+use crate::{
+    image::{similarity::PerceptualHash, ImageError, SavedImage},
+    BASEDIRECTORIES, CONFIG,
+};
+use filter::{Dimensions, ImageFilter};
+use manifest::{get_file_name, CollectionManifest, ImageMetadata};
+use usage::{CollectionUsage, SelectionStrategy};
 
 const GIT_REMOTE_NAME: &str = r#"origin"#;
 
@@ -35,10 +43,43 @@ pub enum CollectionError {
     CollectionEmpty,
     #[error("There is no storage location for collections, is the path occupied?")]
     NoStorageLocation,
-    #[error("The collection repository could not be initialized: {0}")]
+    #[error("The collection manifest could not be read: {0}")]
     DecodeError(toml::de::Error),
-    #[error("The collection repository could not be saved: {0}")]
+    #[error("The collection manifest could not be saved: {0}")]
     EncodeError(toml::ser::Error),
+    #[error("The image could not be copied into the collection: {0}")]
+    ImageError(ImageError),
+    #[error("The image is already in the collection as: {0}")]
+    DuplicateImage(String),
+    #[error("Another image in the collection already uses the name: {0}")]
+    NameTaken(String),
+    #[error("The aspect ratio or resolution is invalid: {0}")]
+    InvalidFilter(String),
+    #[error("No image in the collection matches the aspect ratios and minimum resolution")]
+    NoMatchingImage,
+}
+
+/// How an image is brought into a collection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImportMode {
+    #[default]
+    Copy,
+    /// Copy the image and remove the original.
+    Move,
+    /// Link to the original image, the image itself is not synced through git.
+    Symlink,
+}
+
+/// What to do when an added image is already in the collection, or its name is taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Keep the image already in the collection.
+    #[default]
+    Skip,
+    /// Add the image under a free name, identical images are still skipped.
+    Rename,
+    /// Replace the image already in the collection.
+    Replace,
 }
 
 #[derive(Debug, Clone)]
@@ -73,7 +114,7 @@ impl CollectionPath {
 
     pub fn create_directory(&self) -> Result<(), CollectionError> {
         if !self.exists() {
-            match std::fs::DirBuilder::new().create(&self) {
+            match std::fs::DirBuilder::new().create(self) {
                 Ok(_) => Ok(()),
                 Err(err) => {
                     panic!("{:?}", err);
@@ -101,6 +142,30 @@ impl CollectionPath {
 
         Ok(goal_path)
     }
+
+    /// Like 'get_image_path', but numbers the name if it is taken already
+    pub fn get_free_image_path(&self, image: &SavedImage) -> Result<PathBuf, CollectionError> {
+        let goal_path = self.get_image_path(image)?;
+
+        let (stem, extension) = match (goal_path.file_stem(), goal_path.extension()) {
+            (Some(stem), Some(extension)) => (stem.to_string_lossy(), extension.to_string_lossy()),
+            _ => {
+                return Err(CollectionError::InvalidName(
+                    goal_path.to_string_lossy().into(),
+                ))
+            }
+        };
+
+        let mut index = 1;
+        let mut free_path = goal_path.clone();
+        // Also catches broken symlinks, which 'exists' does not
+        while free_path.symlink_metadata().is_ok() {
+            free_path = self.0.join(format!("{}-{}.{}", stem, index, extension));
+            index += 1;
+        }
+
+        Ok(free_path)
+    }
 }
 
 impl AsRef<Path> for CollectionPath {
@@ -148,14 +213,14 @@ impl CollectionRepository {
         P: AsRef<Path>,
     {
         // Inner function purely for clean error handling
-        fn inner<'b>(path: &Path, remote_url: &str) -> Result<(), git2::Error> {
+        fn inner(path: &Path, remote_url: &str) -> Result<(), git2::Error> {
             let repository = Repository::init(path)?;
 
-            repository.remote(GIT_REMOTE_NAME, &remote_url)?;
+            repository.remote(GIT_REMOTE_NAME, remote_url)?;
 
             repository
                 .index()?
-                .add_all(&["."], git2::IndexAddOption::DEFAULT, None)?;
+                .add_all(["."], git2::IndexAddOption::DEFAULT, None)?;
 
             // Initial commit
 
@@ -194,7 +259,7 @@ impl CollectionRepository {
         match Repository::open(path.as_ref()) {
             Ok(repository) => Ok(CollectionRepository(repository)),
             Err(err) if err.code() == git2::ErrorCode::NotFound => Err(CollectionError::NoGitFound),
-            Err(err) => return Err(CollectionError::GitError(err)),
+            Err(err) => Err(CollectionError::GitError(err)),
         }
     }
 
@@ -207,20 +272,21 @@ impl CollectionRepository {
 
         match builder.clone(remote_url, path.as_ref()) {
             Ok(repository) => Ok(CollectionRepository(repository)),
-            Err(err) => return Err(CollectionError::GitError(err)),
+            Err(err) => Err(CollectionError::GitError(err)),
         }
     }
 
     pub fn commit_all(&self, message: &str) -> Result<(), CollectionError> {
         fn inner(repository: &Repository, message: &str) -> Result<(), git2::Error> {
-            repository
-                .index()?
-                .add_all(&["."], git2::IndexAddOption::DEFAULT, None)?;
+            let mut index = repository.index()?;
+            index.add_all(["."], git2::IndexAddOption::DEFAULT, None)?;
+            // Written to disk, otherwise the committed files show up as deleted from the index
+            index.write()?;
 
             let head = repository.head()?;
 
             let signature = repository.signature()?;
-            let oid = repository.index()?.write_tree()?;
+            let oid = index.write_tree()?;
             let tree = repository.find_tree(oid)?;
             repository.commit(
                 Some("HEAD"),
@@ -234,7 +300,7 @@ impl CollectionRepository {
             Ok(())
         }
 
-        inner(&self.0, message).map_err(|err| CollectionError::GitError(err))
+        inner(&self.0, message).map_err(CollectionError::GitError)
     }
 
     pub fn sync(&self) -> Result<(), CollectionError> {
@@ -255,13 +321,14 @@ impl CollectionRepository {
         }
 
         inner(&self.0, Self::get_fetch_options(), Self::get_push_options())
-            .map_err(|err| CollectionError::GitError(err))
+            .map_err(CollectionError::GitError)
     }
 }
 
 pub struct CollectionDirectory {
     path: CollectionPath,
     repository: Option<CollectionRepository>,
+    manifest: CollectionManifest,
 }
 
 impl CollectionDirectory {
@@ -289,12 +356,20 @@ impl CollectionDirectory {
         let path = CollectionPath::from_name(name)?;
         path.create_directory()?;
 
+        // Written before initializing the repository, so it is part of the initial commit
+        let manifest = CollectionManifest::default();
+        manifest.write(&path)?;
+
         let repository = match remote_url {
             Some(url) => Some(CollectionRepository::initialize(&path, url)?),
             None => None,
         };
 
-        Ok(Self { path, repository })
+        Ok(Self {
+            path,
+            repository,
+            manifest,
+        })
     }
 
     fn open(name: &str) -> Result<Self, CollectionError> {
@@ -309,8 +384,13 @@ impl CollectionDirectory {
                 Err(CollectionError::NoGitFound) => None,
                 Err(err) => return Err(err),
             };
+            let manifest = CollectionManifest::read(&path)?;
 
-            Ok(Self { path, repository })
+            Ok(Self {
+                path,
+                repository,
+                manifest,
+            })
         } else {
             Err(CollectionError::CollectionNotFound)
         }
@@ -328,13 +408,18 @@ impl CollectionDirectory {
                 Ok(repository) => Some(repository),
                 Err(err) => return Err(err),
             };
+            let manifest = CollectionManifest::read(&path)?;
 
-            Ok(Self { path, repository })
+            Ok(Self {
+                path,
+                repository,
+                manifest,
+            })
         }
     }
 
     fn delete(self) -> Result<(), CollectionError> {
-        std::fs::remove_dir_all(self.path).map_err(|err| CollectionError::FsError(err))
+        std::fs::remove_dir_all(self.path).map_err(CollectionError::FsError)
     }
 
     // Getters
@@ -360,6 +445,19 @@ impl CollectionDirectory {
         &self.path
     }
 
+    pub fn get_manifest(&self) -> &CollectionManifest {
+        &self.manifest
+    }
+
+    /// Changes to the manifest are written with 'save_manifest'
+    pub fn get_manifest_mut(&mut self) -> &mut CollectionManifest {
+        &mut self.manifest
+    }
+
+    pub fn save_manifest(&self) -> Result<(), CollectionError> {
+        self.manifest.write(&self.path)
+    }
+
     // Files
 
     pub fn get_images(&self) -> Result<Vec<SavedImage>, CollectionError> {
@@ -371,8 +469,14 @@ impl CollectionDirectory {
         fn extract_images(entry: Result<DirEntry, io::Error>) -> Option<SavedImage> {
             match entry {
                 Ok(entry) if ImageFormat::from_path(entry.path()).is_ok() => {
-                    // TODO: Get rid of the unwrap here, and make it return none if the image errored due to an invalid file type
-                    Some(SavedImage::from_path(entry.path()).unwrap())
+                    // Symlinked images break when the original is moved or deleted
+                    match SavedImage::from_path(entry.path()) {
+                        Ok(image) => Some(image),
+                        Err(err) => {
+                            println!("Skipping image: {:?}, reason: {}", entry.path(), err);
+                            None
+                        }
+                    }
                 }
                 Ok(_) => None,
                 Err(_) => None,
@@ -383,13 +487,88 @@ impl CollectionDirectory {
         Ok(images.collect())
     }
 
-    pub fn get_random_image(&self) -> Result<SavedImage, CollectionError> {
-        // Get images, filter for type and pick a random one
-        // TODO: Make the random pick a bit less random and more likely to pick the least used one
-        let image = self
-            .get_images()?
-            .into_iter()
-            .choose(&mut rand::thread_rng());
+    /// The strategy for picking images from the manifest, or the global default
+    pub fn get_selection_strategy(&self) -> SelectionStrategy {
+        self.manifest
+            .selection_strategy
+            .unwrap_or(CONFIG.selection_strategy)
+    }
+
+    /// The width and height of every image, read from the image headers if missing from the manifest
+    pub fn get_image_dimensions(
+        &mut self,
+    ) -> Result<Vec<(SavedImage, Dimensions)>, CollectionError> {
+        let mut dimensions = vec![];
+        let mut manifest_changed = false;
+
+        for image in self.get_images()? {
+            let metadata = self.manifest.get_image_mut(&image);
+
+            let image_dimensions = match (metadata.width, metadata.height) {
+                (Some(width), Some(height)) => (width, height),
+                _ => match image::image_dimensions(image.get_path()) {
+                    Ok((width, height)) => {
+                        metadata.width = Some(width);
+                        metadata.height = Some(height);
+                        manifest_changed = true;
+
+                        (width, height)
+                    }
+                    // Not every file with an image extension can be read
+                    Err(_) => continue,
+                },
+            };
+
+            dimensions.push((image, image_dimensions));
+        }
+
+        if manifest_changed {
+            self.save_manifest()?;
+        }
+
+        Ok(dimensions)
+    }
+
+    /// Pick an image with the strategy, or the configured strategy if not given.
+    /// Only images passing the filter are picked, which is the filter of the config if not given.
+    pub fn get_random_image(
+        &mut self,
+        strategy: Option<SelectionStrategy>,
+        filter: Option<&ImageFilter>,
+    ) -> Result<SavedImage, CollectionError> {
+        let strategy = strategy.unwrap_or(self.get_selection_strategy());
+
+        let config_filter;
+        let filter = match filter {
+            Some(filter) => filter,
+            None => {
+                config_filter = ImageFilter::from_config()?;
+                &config_filter
+            }
+        };
+
+        let images = if filter.is_empty() {
+            self.get_images()?
+        } else {
+            let dimensions = self.get_image_dimensions()?;
+            if dimensions.is_empty() {
+                return Err(CollectionError::CollectionEmpty);
+            }
+
+            let images = dimensions
+                .into_iter()
+                .filter(|(_, (width, height))| filter.matches(*width, *height))
+                .map(|(image, _)| image)
+                .collect::<Vec<_>>();
+            if images.is_empty() {
+                return Err(CollectionError::NoMatchingImage);
+            }
+
+            images
+        };
+
+        let mut usage = CollectionUsage::open(&self.path)?;
+        let image = usage.pick(images, strategy, &self.manifest);
 
         match image {
             Some(image) => Ok(image),
@@ -409,13 +588,195 @@ impl CollectionDirectory {
         }
     }
 
-    pub fn add_image(&self, image: &SavedImage) -> Result<SavedImage, CollectionError> {
-        let goal_path = self.path.get_image_path(&image)?;
+    /// The content hash of every image, hashes missing from the manifest are added to it
+    pub fn get_image_hashes(&mut self) -> Result<Vec<(SavedImage, String)>, CollectionError> {
+        let mut hashes = vec![];
+        let mut manifest_changed = false;
+
+        for image in self.get_images()? {
+            let metadata = self.manifest.get_image_mut(&image);
+            let content_hash = match &metadata.content_hash {
+                Some(content_hash) => content_hash.clone(),
+                None => {
+                    let content_hash = image
+                        .get_content_hash()
+                        .map_err(CollectionError::ImageError)?;
+                    metadata.content_hash = Some(content_hash.clone());
+                    manifest_changed = true;
+
+                    content_hash
+                }
+            };
+
+            hashes.push((image, content_hash));
+        }
+
+        if manifest_changed {
+            self.save_manifest()?;
+        }
+
+        Ok(hashes)
+    }
+
+    /// The perceptual hash of every image that can be decoded, hashes missing from the manifest are added to it
+    pub fn get_perceptual_hashes(
+        &mut self,
+    ) -> Result<Vec<(SavedImage, PerceptualHash)>, CollectionError> {
+        let mut hashes = vec![];
+        let mut manifest_changed = false;
+
+        for image in self.get_images()? {
+            let metadata = self.manifest.get_image_mut(&image);
+            let stored_hash = metadata
+                .perceptual_hash
+                .as_ref()
+                .and_then(|hash| hash.parse().ok());
+
+            let perceptual_hash = match stored_hash {
+                Some(perceptual_hash) => perceptual_hash,
+                None => match image.get_perceptual_hash() {
+                    Ok(perceptual_hash) => {
+                        metadata.perceptual_hash = Some(perceptual_hash.to_string());
+                        manifest_changed = true;
+
+                        perceptual_hash
+                    }
+                    // Not every file with an image extension can be decoded
+                    Err(_) => continue,
+                },
+            };
+
+            hashes.push((image, perceptual_hash));
+        }
+
+        if manifest_changed {
+            self.save_manifest()?;
+        }
+
+        Ok(hashes)
+    }
+
+    /// Deletes the image file and its metadata
+    pub fn remove_image(&mut self, image: &SavedImage) -> Result<(), CollectionError> {
+        match std::fs::remove_file(image.get_path()) {
+            Ok(_) => {}
+            Err(err) => return Err(CollectionError::FsError(err)),
+        }
+
+        self.manifest.remove_image(image);
+        self.save_manifest()
+    }
+
+    /// Copies the image into the collection and stores its metadata in the manifest
+    pub fn add_image(
+        &mut self,
+        image: &SavedImage,
+        metadata: ImageMetadata,
+        on_duplicate: DuplicatePolicy,
+    ) -> Result<SavedImage, CollectionError> {
+        self.import_image(image, metadata, ImportMode::Copy, on_duplicate)
+    }
+
+    /// Brings the image into the collection as the mode says and stores its metadata in the manifest
+    pub fn import_image(
+        &mut self,
+        image: &SavedImage,
+        metadata: ImageMetadata,
+        mode: ImportMode,
+        on_duplicate: DuplicatePolicy,
+    ) -> Result<SavedImage, CollectionError> {
+        let content_hash = image
+            .get_content_hash()
+            .map_err(CollectionError::ImageError)?;
+
+        let duplicate = self
+            .get_image_hashes()?
+            .into_iter()
+            .find(|(_, hash)| *hash == content_hash)
+            .map(|(duplicate, _)| duplicate);
+
+        if let Some(duplicate) = duplicate {
+            let is_same_file = match (
+                std::fs::canonicalize(duplicate.get_path()),
+                std::fs::canonicalize(image.get_path()),
+            ) {
+                (Ok(duplicate_path), Ok(image_path)) => duplicate_path == image_path,
+                _ => false,
+            };
 
-        match image.copy_to(goal_path) {
-            Ok(image) => Ok(image),
-            Err(_err) => panic!("Implement actual error handling here\nFailed to copy image"),
+            match on_duplicate {
+                DuplicatePolicy::Replace if !is_same_file => self.remove_image(&duplicate)?,
+                _ => return Err(CollectionError::DuplicateImage(get_file_name(&duplicate))),
+            }
+        }
+
+        let mut goal_path = self.path.get_image_path(image)?;
+
+        // The name is taken by a different image
+        if goal_path.symlink_metadata().is_ok() {
+            let file_name = goal_path
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            match on_duplicate {
+                DuplicatePolicy::Skip => return Err(CollectionError::NameTaken(file_name)),
+                DuplicatePolicy::Rename => goal_path = self.path.get_free_image_path(image)?,
+                DuplicatePolicy::Replace => {
+                    match std::fs::remove_file(&goal_path) {
+                        Ok(_) => {}
+                        Err(err) => return Err(CollectionError::FsError(err)),
+                    }
+                    self.manifest.images.remove(&file_name);
+                }
+            }
         }
+
+        let image = match mode {
+            ImportMode::Copy => image
+                .copy_to(goal_path)
+                .map_err(CollectionError::ImageError)?,
+            ImportMode::Move => {
+                let copied = image
+                    .copy_to(goal_path)
+                    .map_err(CollectionError::ImageError)?;
+                match std::fs::remove_file(image.get_path()) {
+                    Ok(_) => {}
+                    Err(err) => return Err(CollectionError::FsError(err)),
+                }
+
+                copied
+            }
+            ImportMode::Symlink => {
+                let source = match std::fs::canonicalize(image.get_path()) {
+                    Ok(source) => source,
+                    Err(err) => return Err(CollectionError::FsError(err)),
+                };
+                match std::os::unix::fs::symlink(source, &goal_path) {
+                    Ok(_) => {}
+                    Err(err) => return Err(CollectionError::FsError(err)),
+                }
+
+                SavedImage::from_path(goal_path).map_err(CollectionError::ImageError)?
+            }
+        };
+        let dimensions = image::image_dimensions(image.get_path()).ok();
+        self.manifest.add_image(
+            &image,
+            ImageMetadata {
+                content_hash: Some(content_hash),
+                perceptual_hash: image
+                    .get_perceptual_hash()
+                    .ok()
+                    .map(|perceptual_hash| perceptual_hash.to_string()),
+                width: dimensions.map(|(width, _)| width),
+                height: dimensions.map(|(_, height)| height),
+                ..metadata
+            },
+        );
+        self.save_manifest()?;
+
+        Ok(image)
     }
 }
 
@@ -465,15 +826,11 @@ impl Collection {
             Ok(files) => files,
             Err(err) => return Err(CollectionError::FsError(err)),
         }
-        .filter_map(|entry| match entry {
-            Ok(entry) => Some(entry),
-            Err(_) => None,
-        })
+        .filter_map(|entry| entry.ok())
         .filter_map(|entry| match entry.path() {
-            path if path.is_dir() => match path.file_stem() {
-                Some(stem) => Some(stem.to_string_lossy().into_owned()),
-                None => None,
-            },
+            path if path.is_dir() => path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned()),
             _ => None,
         });
 
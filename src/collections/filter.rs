@@ -0,0 +1,181 @@
+use crate::CONFIG;
+
+use super::CollectionError;
+
+/// How far an image may be off an aspect ratio and still match, relative to the ratio
+pub const DEFAULT_ASPECT_RATIO_TOLERANCE: f64 = 0.05;
+
+/// The width and height of an image
+pub type Dimensions = (u32, u32);
+
+/// Limits the images picked from a collection by their shape and size
+#[derive(Debug, Clone, Default)]
+pub struct ImageFilter {
+    /// Width divided by height, any ratio matches if empty
+    aspect_ratios: Vec<f64>,
+    tolerance: f64,
+    min_width: u32,
+    min_height: u32,
+}
+
+/// Parses 'width:height' or 'widthxheight' into its two parts
+fn parse_pair(value: &str) -> Option<(u32, u32)> {
+    let (first, second) = value.split_once([':', 'x'])?;
+
+    Some((first.trim().parse().ok()?, second.trim().parse().ok()?))
+}
+
+/// Whether the aspect ratio is a 'width:height' ratio, suppliers also take names like 'landscape'
+pub fn is_aspect_ratio(value: &str) -> bool {
+    parse_pair(value).is_some_and(|(_, height)| height > 0)
+}
+
+impl ImageFilter {
+    pub fn new(
+        aspect_ratios: &[String],
+        tolerance: f64,
+        min_resolution: Option<&str>,
+    ) -> Result<Self, CollectionError> {
+        let aspect_ratios = aspect_ratios
+            .iter()
+            .map(|aspect_ratio| match parse_pair(aspect_ratio) {
+                Some((width, height)) if height > 0 => Ok(width as f64 / height as f64),
+                _ => Err(CollectionError::InvalidFilter(aspect_ratio.to_owned())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (min_width, min_height) = match min_resolution {
+            Some(min_resolution) => parse_pair(min_resolution)
+                .ok_or(CollectionError::InvalidFilter(min_resolution.to_owned()))?,
+            None => (0, 0),
+        };
+
+        Ok(Self {
+            aspect_ratios,
+            tolerance,
+            min_width,
+            min_height,
+        })
+    }
+
+    /// The aspect ratios and minimum resolution of the config.
+    /// Aspect ratios suppliers take that aren't 'width:height', like 'landscape', are left out.
+    /// The config warns about them once when it is read.
+    pub fn from_config() -> Result<Self, CollectionError> {
+        let aspect_ratios: Vec<String> = CONFIG
+            .aspect_ratios
+            .iter()
+            .filter(|aspect_ratio| is_aspect_ratio(aspect_ratio))
+            .cloned()
+            .collect();
+
+        Self::new(
+            &aspect_ratios,
+            CONFIG
+                .aspect_ratio_tolerance
+                .unwrap_or(DEFAULT_ASPECT_RATIO_TOLERANCE),
+            CONFIG.min_resolution.as_deref(),
+        )
+    }
+
+    /// A filter letting every image through
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aspect_ratios.is_empty() && self.min_width == 0 && self.min_height == 0
+    }
+
+    pub fn matches(&self, width: u32, height: u32) -> bool {
+        if width < self.min_width || height < self.min_height {
+            return false;
+        }
+
+        if self.aspect_ratios.is_empty() {
+            return true;
+        }
+        if height == 0 {
+            return false;
+        }
+
+        let ratio = width as f64 / height as f64;
+        self.aspect_ratios
+            .iter()
+            .any(|aspect_ratio| (ratio - aspect_ratio).abs() <= aspect_ratio * self.tolerance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_aspect_ratios() {
+        assert!(is_aspect_ratio("16:9"));
+        assert!(is_aspect_ratio("1920x1080"));
+        assert!(!is_aspect_ratio("landscape"));
+        assert!(!is_aspect_ratio("16:0"));
+        assert!(!is_aspect_ratio("16:"));
+    }
+
+    #[test]
+    fn matches_aspect_ratios_within_the_tolerance() {
+        let filter = ImageFilter::new(&["16:9".to_owned(), "4:3".to_owned()], 0.05, None).unwrap();
+
+        assert!(filter.matches(1920, 1080));
+        assert!(filter.matches(1920, 1100), "within 5% of 16:9");
+        assert!(
+            !filter.matches(1920, 1200),
+            "16:10 is more than 5% off 16:9"
+        );
+        assert!(filter.matches(1024, 768));
+        assert!(!filter.matches(2560, 1080));
+        assert!(!filter.matches(1080, 1920));
+        assert!(!filter.matches(1920, 0));
+    }
+
+    #[test]
+    fn exact_ratios_without_tolerance() {
+        let filter = ImageFilter::new(&["16:9".to_owned()], 0.0, None).unwrap();
+
+        assert!(filter.matches(3840, 2160));
+        assert!(!filter.matches(1920, 1200));
+    }
+
+    #[test]
+    fn matches_the_minimum_resolution() {
+        let filter = ImageFilter::new(&[], 0.05, Some("1920x1080")).unwrap();
+
+        assert!(filter.matches(1920, 1080));
+        assert!(filter.matches(3840, 1080));
+        assert!(!filter.matches(1919, 1080));
+        assert!(!filter.matches(1920, 1079));
+    }
+
+    #[test]
+    fn combines_ratio_and_resolution() {
+        let filter = ImageFilter::new(&["16:9".to_owned()], 0.05, Some("1920x1080")).unwrap();
+
+        assert!(filter.matches(2560, 1440));
+        assert!(!filter.matches(1280, 720));
+        assert!(!filter.matches(2560, 2000));
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = ImageFilter::none();
+
+        assert!(filter.is_empty());
+        assert!(filter.matches(1, 1));
+        assert!(filter.matches(0, 0));
+        assert!(!ImageFilter::new(&[], 0.05, Some("1x1")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        assert!(ImageFilter::new(&["landscape".to_owned()], 0.05, None).is_err());
+        assert!(ImageFilter::new(&["16:0".to_owned()], 0.05, None).is_err());
+        assert!(ImageFilter::new(&[], 0.05, Some("large")).is_err());
+    }
+}
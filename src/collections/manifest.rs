@@ -0,0 +1,120 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::SetCommandConfig, image::SavedImage};
+
+use super::{usage::SelectionStrategy, CollectionError, CollectionPath};
+
+/// The name of the manifest file inside a collection directory
+pub const MANIFEST_FILE_NAME: &str = "collection.toml";
+
+/// Where an image came from and how it is described
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ImageMetadata {
+    pub source_url: Option<String>,
+    pub supplier: Option<String>,
+    /// The id of the image at the supplier
+    pub original_id: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// A rating from 0 to 5, 0 meaning the image is never picked by rating
+    pub rating: Option<u8>,
+    /// Seconds since the unix epoch
+    pub date_added: Option<u64>,
+    /// The sha256 hash of the image file, used to find duplicates
+    pub content_hash: Option<String>,
+    /// The difference hash of the picture, used to find near duplicates
+    pub perceptual_hash: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// The settings and image metadata of a collection, stored in the collection so it gets synced with the images
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CollectionManifest {
+    pub description: Option<String>,
+    /// Tags given to images added without tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Used instead of the 'set_command' of the config for images of this collection
+    pub set_command: Option<SetCommandConfig>,
+    /// Effects applied to the images of this collection when they are set, like 'blur=12'
+    #[serde(default)]
+    pub effects: Vec<String>,
+    pub selection_strategy: Option<SelectionStrategy>,
+    /// Keyed by image file name
+    #[serde(default)]
+    pub images: BTreeMap<String, ImageMetadata>,
+}
+
+/// The file name of the image, which the manifest uses as key
+pub fn get_file_name(image: &SavedImage) -> String {
+    image
+        .get_path()
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl CollectionManifest {
+    fn get_manifest_path(collection_path: &CollectionPath) -> PathBuf {
+        collection_path.as_ref().join(MANIFEST_FILE_NAME)
+    }
+
+    /// Reads the manifest of the collection, an empty manifest if the collection has none
+    pub fn read(collection_path: &CollectionPath) -> Result<Self, CollectionError> {
+        match std::fs::read_to_string(Self::get_manifest_path(collection_path)) {
+            Ok(file_content) => toml::from_str(&file_content).map_err(CollectionError::DecodeError),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(CollectionError::FsError(err)),
+        }
+    }
+
+    pub fn write(&self, collection_path: &CollectionPath) -> Result<(), CollectionError> {
+        let file_content = toml::to_string(self).map_err(CollectionError::EncodeError)?;
+
+        std::fs::write(Self::get_manifest_path(collection_path), file_content)
+            .map_err(CollectionError::FsError)
+    }
+
+    pub fn get_image(&self, image: &SavedImage) -> Option<&ImageMetadata> {
+        self.images.get(&get_file_name(image))
+    }
+
+    pub fn get_image_mut(&mut self, image: &SavedImage) -> &mut ImageMetadata {
+        self.images.entry(get_file_name(image)).or_default()
+    }
+
+    pub fn remove_image(&mut self, image: &SavedImage) -> Option<ImageMetadata> {
+        self.images.remove(&get_file_name(image))
+    }
+
+    /// Stores the metadata of a newly added image, using the default tags if it has none
+    pub fn add_image(&mut self, image: &SavedImage, metadata: ImageMetadata) {
+        let date_added = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        let tags = if metadata.tags.is_empty() {
+            self.tags.clone()
+        } else {
+            metadata.tags
+        };
+
+        self.images.insert(
+            get_file_name(image),
+            ImageMetadata {
+                tags,
+                date_added: Some(date_added),
+                ..metadata
+            },
+        );
+    }
+}
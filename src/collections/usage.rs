@@ -0,0 +1,184 @@
+use std::{
+    collections::BTreeMap,
+    io,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rand::{
+    distributions::WeightedIndex,
+    prelude::Distribution,
+    seq::{IteratorRandom, SliceRandom},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{image::SavedImage, BASEDIRECTORIES};
+
+use super::{
+    manifest::{get_file_name, CollectionManifest},
+    CollectionError, CollectionPath,
+};
+
+/// The rating used for weighing images without a rating
+const DEFAULT_RATING: u8 = 3;
+
+/// How an image is picked from a collection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SelectionStrategy {
+    /// Every image has the same chance
+    #[default]
+    Uniform,
+    /// The image shown longest ago, or never shown
+    LeastRecentlyUsed,
+    /// A random image, weighted by its rating
+    Rating,
+    /// Every image once in a random order, before any repeats
+    Shuffle,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ImageUsage {
+    /// Seconds since the unix epoch
+    pub last_shown: Option<u64>,
+    pub times_shown: u64,
+}
+
+/// The usage statistics of the images in a collection, kept outside of the collection so they don't get synced
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CollectionUsage {
+    #[serde(skip)]
+    path: PathBuf,
+    /// Keyed by image file name
+    #[serde(default)]
+    images: BTreeMap<String, ImageUsage>,
+    /// The image file names left to show with the shuffle strategy
+    #[serde(default)]
+    playlist: Vec<String>,
+}
+
+impl CollectionUsage {
+    fn get_storage_directory() -> PathBuf {
+        BASEDIRECTORIES.data_dir().join("usage")
+    }
+
+    pub fn open(collection_path: &CollectionPath) -> Result<Self, CollectionError> {
+        let storage_directory = Self::get_storage_directory();
+        std::fs::create_dir_all(&storage_directory).map_err(CollectionError::FsError)?;
+
+        let name = match collection_path.as_ref().file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Err(CollectionError::CollectionNotFound),
+        };
+        let path = storage_directory.join(format!("{}.toml", name));
+
+        let mut usage: Self = match std::fs::read_to_string(&path) {
+            Ok(file_content) => toml::from_str(&file_content).unwrap_or_default(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Self::default(),
+            Err(err) => return Err(CollectionError::FsError(err)),
+        };
+        usage.path = path;
+
+        Ok(usage)
+    }
+
+    pub fn get_usage(&self, image: &SavedImage) -> Option<&ImageUsage> {
+        self.images.get(&get_file_name(image))
+    }
+
+    pub fn record_shown(&mut self, image: &SavedImage) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        let usage = self.images.entry(get_file_name(image)).or_default();
+        usage.last_shown = Some(timestamp);
+        usage.times_shown += 1;
+    }
+
+    fn pick_least_recently_used(&self, images: Vec<SavedImage>) -> Option<SavedImage> {
+        let get_last_shown =
+            |image: &SavedImage| self.get_usage(image).and_then(|usage| usage.last_shown);
+
+        // Never shown images come first, seeing None is smaller than any value
+        let oldest = images.iter().map(get_last_shown).min()?;
+
+        images
+            .into_iter()
+            .filter(|image| get_last_shown(image) == oldest)
+            .choose(&mut rand::thread_rng())
+    }
+
+    fn pick_by_rating(
+        images: Vec<SavedImage>,
+        manifest: &CollectionManifest,
+    ) -> Option<SavedImage> {
+        let weights = images.iter().map(|image| {
+            manifest
+                .get_image(image)
+                .and_then(|metadata| metadata.rating)
+                .unwrap_or(DEFAULT_RATING) as u32
+        });
+
+        match WeightedIndex::new(weights) {
+            Ok(distribution) => images
+                .into_iter()
+                .nth(distribution.sample(&mut rand::thread_rng())),
+            // All images are rated 0, so they are all equally likely, or there are no images
+            Err(_) => images.into_iter().choose(&mut rand::thread_rng()),
+        }
+    }
+
+    fn pick_from_playlist(&mut self, images: Vec<SavedImage>) -> Option<SavedImage> {
+        if images.is_empty() {
+            return None;
+        }
+
+        let mut images: BTreeMap<String, SavedImage> = images
+            .into_iter()
+            .map(|image| (get_file_name(&image), image))
+            .collect();
+
+        loop {
+            if self.playlist.is_empty() {
+                self.playlist = images.keys().cloned().collect();
+                self.playlist.shuffle(&mut rand::thread_rng());
+            }
+
+            // Images removed since the playlist was made are skipped
+            let file_name = self.playlist.remove(0);
+            if let Some(image) = images.remove(&file_name) {
+                return Some(image);
+            }
+        }
+    }
+
+    /// Picks an image using the strategy, and records it as shown
+    pub fn pick(
+        &mut self,
+        images: Vec<SavedImage>,
+        strategy: SelectionStrategy,
+        manifest: &CollectionManifest,
+    ) -> Option<SavedImage> {
+        let image = match strategy {
+            SelectionStrategy::Uniform => images.into_iter().choose(&mut rand::thread_rng()),
+            SelectionStrategy::LeastRecentlyUsed => self.pick_least_recently_used(images),
+            SelectionStrategy::Rating => Self::pick_by_rating(images, manifest),
+            SelectionStrategy::Shuffle => self.pick_from_playlist(images),
+        }?;
+
+        self.record_shown(&image);
+
+        Some(image)
+    }
+}
+
+impl Drop for CollectionUsage {
+    fn drop(&mut self) {
+        let file_content =
+            toml::to_string(&self).expect("Failed to serialize collection usage to TOML");
+        std::fs::write(&self.path, file_content)
+            .expect("Failed to write collection usage to file.");
+    }
+}
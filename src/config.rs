@@ -1,17 +1,33 @@
-use std::{
-    io::Read,
-    path::{Path, PathBuf},
-};
+use std::{io::Read, path::Path};
+
+use serde::{Deserialize, Serialize};
 
-use serde::Deserialize;
+use crate::{
+    collections::{filter::is_aspect_ratio, usage::SelectionStrategy},
+    image::processing::ScaleMode,
+    state::backend::BackendKind,
+    BASEDIRECTORIES,
+};
 
-use crate::BASEDIRECTORIES;
+/// The command assigning a wallpaper, as a command line or as the program followed by its arguments
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum SetCommandConfig {
+    Line(String),
+    Args(Vec<String>),
+}
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct CategoryConfig {
     pub name: String,
     pub tags: Vec<String>,
+    /// Tags to exclude, on top of the global excluded tags
+    #[serde(default)]
+    pub exclude_tags: Vec<String>,
     pub aspect_ratios: Option<Vec<String>>,
+    /// Effects applied to the images of the category when they are set, like 'blur=12'
+    #[serde(default)]
+    pub effects: Vec<String>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -20,9 +36,119 @@ pub struct SupplierFile {
     pub file: String,
 }
 
+/// Where the daemon picks the next wallpaper from
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum DaemonSource {
+    #[serde(alias = "collection", alias = "COLLECTION")]
+    Collection { name: String },
+    #[serde(alias = "category", alias = "CATEGORY")]
+    Category { name: String },
+    #[serde(alias = "fetch", alias = "FETCH")]
+    Fetch,
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct DaemonConfig {
+    /// The amount of seconds between wallpaper changes
+    pub interval: Option<u64>,
+    /// A cron expression for the wallpaper changes, used instead of the interval if set
+    pub schedule: Option<String>,
+    /// The sources to pick from, a random one is picked for every change
+    #[serde(default)]
+    pub sources: Vec<DaemonSource>,
+    /// The path of the control socket, defaults to the runtime directory
+    pub socket_path: Option<String>,
+}
+
+/// How images are scaled to the screen before they are assigned
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct ProcessingConfig {
+    #[serde(default)]
+    pub mode: ScaleMode,
+    /// The 'widthxheight' to scale to, images are left as is if not set
+    pub resolution: Option<String>,
+    /// The '#rrggbb' colour around images scaled with fit
+    pub background: Option<String>,
+}
+
+/// Runs after a wallpaper is set, '{background}', '{foreground}', '{color0}' up to '{color7}' and '{wallpaper}' are filled
+/// into the template, the command gets them as 'WALLTZ_' environment variables
+#[derive(Deserialize, Clone, Debug)]
+pub struct PostSetHook {
+    /// The template file, relative to the config directory
+    pub template: Option<String>,
+    /// Where the filled in template is written, relative to the config directory
+    pub output: Option<String>,
+    /// A shell command run after the template is written, with 'WALLTZ_WALLPAPER', 'WALLTZ_BACKGROUND' and the like set
+    pub command: Option<String>,
+}
+
+/// Shell commands run around changes of the wallpaper
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct HooksConfig {
+    /// Run before a wallpaper is set, a failing hook stops the change
+    #[serde(default)]
+    pub pre_set: Vec<String>,
+    /// Run after the wallpaper is assigned
+    #[serde(default)]
+    pub post_set: Vec<String>,
+    /// Run after an image is downloaded
+    #[serde(default)]
+    pub post_fetch: Vec<String>,
+    /// Run when a command fails, with the message in 'WALLTZ_ERROR'
+    #[serde(default)]
+    pub on_error: Vec<String>,
+    /// The seconds a hook may run before it is stopped
+    pub timeout: Option<u64>,
+}
+
+/// Where the file backend puts the wallpaper
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct FileBackendConfig {
+    /// Relative to the config directory, '{output}' is replaced by the output name or 'all'
+    pub path: Option<String>,
+    /// Link to the image instead of copying it
+    #[serde(default)]
+    pub symlink: bool,
+}
+
+/// When cached images are removed, the least recently used go first
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct CacheConfig {
+    /// The days a cached image is kept after it was last used
+    pub max_age: Option<u64>,
+    /// The megabytes the cached images may take up
+    pub max_size: Option<u64>,
+    /// The amount of cached images
+    pub max_count: Option<usize>,
+}
+
+/// How requests to suppliers and image hosts are sent
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct NetworkConfig {
+    /// The seconds to wait for a connection
+    pub connect_timeout: Option<u64>,
+    /// The seconds to wait between reads of a response
+    pub read_timeout: Option<u64>,
+    /// How often a failed request is tried again
+    pub retries: Option<u32>,
+    pub user_agent: Option<String>,
+    /// The megabytes an image download may take up
+    pub max_download_size: Option<u64>,
+    /// Search every supplier at the same time when no supplier is given, instead of a random one
+    #[serde(default)]
+    pub concurrent_suppliers: bool,
+}
+
 #[derive(Deserialize, Clone, Debug, Default)]
 pub struct GlobalConfig {
-    pub set_command: Option<String>,
+    /// The program showing the wallpaper, 'custom' runs the set command
+    #[serde(default)]
+    pub backend: BackendKind,
+    #[serde(default)]
+    pub file_backend: FileBackendConfig,
+    pub set_command: Option<SetCommandConfig>,
     pub private_key_path: Option<String>,
     #[serde(default)]
     pub categories: Vec<CategoryConfig>,
@@ -30,6 +156,30 @@ pub struct GlobalConfig {
     pub suppliers: Vec<SupplierFile>,
     #[serde(default)]
     pub aspect_ratios: Vec<String>,
+    /// How far collection images may be off the aspect ratios, relative to the ratio
+    pub aspect_ratio_tolerance: Option<f64>,
+    /// The minimum 'widthxheight' of collection images
+    pub min_resolution: Option<String>,
+    /// Tags to exclude from every search
+    #[serde(default)]
+    pub exclude_tags: Vec<String>,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    /// The amount of wallpapers kept in the history
+    pub history_size: Option<usize>,
+    /// How images are picked from collections without a strategy in their manifest
+    #[serde(default)]
+    pub selection_strategy: SelectionStrategy,
+    #[serde(default)]
+    pub processing: ProcessingConfig,
+    #[serde(default)]
+    pub post_set_hooks: Vec<PostSetHook>,
+    #[serde(default)]
+    pub hooks: HooksConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
 }
 
 impl GlobalConfig {
@@ -37,6 +187,18 @@ impl GlobalConfig {
         BASEDIRECTORIES.config_dir()
     }
 
+    /// Warns about settings that are only partly used, once when the config is read
+    fn warn_unsupported(&self) {
+        for aspect_ratio in &self.aspect_ratios {
+            if !is_aspect_ratio(aspect_ratio) {
+                eprintln!(
+                    "Ignoring the aspect ratio: {} when picking images, it isn't 'width:height'",
+                    aspect_ratio
+                );
+            }
+        }
+    }
+
     pub fn read() -> anyhow::Result<Self> {
         let config_path = Self::get_config_path().join("config.toml");
 
@@ -47,7 +209,10 @@ impl GlobalConfig {
                 let mut buf = String::new();
                 file.read_to_string(&mut buf)?;
 
-                Ok(toml::from_str(&buf)?)
+                let config: Self = toml::from_str(&buf)?;
+                config.warn_unsupported();
+
+                Ok(config)
             }
             Err(_) => Ok(GlobalConfig::default()),
         }
@@ -0,0 +1,363 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::PathBuf,
+    str::FromStr,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rand::seq::SliceRandom;
+use thiserror::Error;
+
+use crate::{
+    category::Category,
+    collections::Collection,
+    config::DaemonSource,
+    hooks::{self, HookContext, HookEvent},
+    image::{effects::Effects, FetchedImage, SearchParameters, UrlSupplier},
+    state::State,
+    BASEDIRECTORIES, CONFIG, HTTPCLIENT, IMAGECACHE,
+};
+
+#[derive(Debug, Error)]
+pub enum DaemonError {
+    #[error("An internal file system error occured: {0}")]
+    FsError(io::Error),
+    #[error("No interval or schedule specified in the daemon config")]
+    NoSchedule,
+    #[error("The schedule is invalid: {0}")]
+    InvalidSchedule(cron::error::Error),
+    #[error("No sources specified in the daemon config")]
+    NoSources,
+    #[error("The daemon is not running")]
+    NotRunning,
+    #[error("The daemon is already running")]
+    AlreadyRunning,
+    #[error("The command is unknown: {0}")]
+    UnknownCommand(String),
+    #[error("The daemon failed to execute the command: {0}")]
+    CommandFailed(String),
+}
+
+/// A command sent to the daemon over the control socket
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DaemonCommand {
+    /// Change to a new wallpaper
+    Next,
+    /// Go back to the previous wallpaper
+    Prev,
+    /// Pause or resume the rotation
+    Pause,
+}
+
+impl DaemonCommand {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Next => "next",
+            Self::Prev => "prev",
+            Self::Pause => "pause",
+        }
+    }
+}
+
+impl FromStr for DaemonCommand {
+    type Err = DaemonError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim() {
+            "next" => Ok(Self::Next),
+            "prev" => Ok(Self::Prev),
+            "pause" => Ok(Self::Pause),
+            value => Err(DaemonError::UnknownCommand(value.to_owned())),
+        }
+    }
+}
+
+fn get_socket_path() -> PathBuf {
+    match &CONFIG.daemon.socket_path {
+        Some(path) => PathBuf::from(path),
+        None => BASEDIRECTORIES
+            .runtime_dir()
+            .unwrap_or(BASEDIRECTORIES.data_dir())
+            .join("daemon.sock"),
+    }
+}
+
+/// Sends a command to the running daemon and returns its reply
+pub fn send_command(command: DaemonCommand) -> Result<String, DaemonError> {
+    let mut stream = match UnixStream::connect(get_socket_path()) {
+        Ok(stream) => stream,
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Err(DaemonError::NotRunning)
+        }
+        Err(err) => return Err(DaemonError::FsError(err)),
+    };
+
+    fn inner(stream: &mut UnixStream, command: DaemonCommand) -> Result<String, io::Error> {
+        writeln!(stream, "{}", command.as_str())?;
+
+        let mut reply = String::new();
+        BufReader::new(stream).read_line(&mut reply)?;
+
+        Ok(reply.trim_end().to_owned())
+    }
+
+    let reply = inner(&mut stream, command).map_err(DaemonError::FsError)?;
+
+    match reply.split_once(' ') {
+        Some(("ok", message)) => Ok(message.to_owned()),
+        Some((_, message)) => Err(DaemonError::CommandFailed(message.to_owned())),
+        None => Err(DaemonError::CommandFailed(reply)),
+    }
+}
+
+enum Schedule {
+    Interval(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    fn from_config() -> Result<Self, DaemonError> {
+        match (&CONFIG.daemon.schedule, CONFIG.daemon.interval) {
+            (Some(schedule), _) => match cron::Schedule::from_str(schedule) {
+                Ok(schedule) => Ok(Self::Cron(Box::new(schedule))),
+                Err(err) => Err(DaemonError::InvalidSchedule(err)),
+            },
+            (None, Some(interval)) => Ok(Self::Interval(Duration::from_secs(interval))),
+            (None, None) => Err(DaemonError::NoSchedule),
+        }
+    }
+
+    /// The moment of the next change, none if the schedule has no upcoming moments
+    fn get_next_change(&self) -> Option<Instant> {
+        match self {
+            Self::Interval(interval) => Some(Instant::now() + *interval),
+            Self::Cron(schedule) => {
+                let next = schedule.upcoming(chrono::Local).next()?;
+                let until_next = (next - chrono::Local::now()).to_std().unwrap_or_default();
+
+                Some(Instant::now() + until_next)
+            }
+        }
+    }
+}
+
+type CommandRequest = (DaemonCommand, mpsc::Sender<Result<String, String>>);
+
+/// Rotates the wallpaper on a schedule, controlled through a unix socket
+pub struct Daemon {
+    schedule: Schedule,
+    sources: Vec<DaemonSource>,
+    paused: bool,
+}
+
+impl Daemon {
+    pub fn from_config() -> Result<Self, DaemonError> {
+        let schedule = Schedule::from_config()?;
+
+        if CONFIG.daemon.sources.is_empty() {
+            return Err(DaemonError::NoSources);
+        }
+
+        Ok(Self {
+            schedule,
+            sources: CONFIG.daemon.sources.clone(),
+            paused: false,
+        })
+    }
+
+    fn bind_socket() -> Result<UnixListener, DaemonError> {
+        let socket_path = get_socket_path();
+
+        if socket_path.exists() {
+            // A socket which can't be connected to is left over from a previous daemon
+            if UnixStream::connect(&socket_path).is_ok() {
+                return Err(DaemonError::AlreadyRunning);
+            }
+            std::fs::remove_file(&socket_path).map_err(DaemonError::FsError)?;
+        } else if let Some(parent) = socket_path.parent() {
+            std::fs::create_dir_all(parent).map_err(DaemonError::FsError)?;
+        }
+
+        UnixListener::bind(&socket_path).map_err(DaemonError::FsError)
+    }
+
+    fn listen(listener: UnixListener, sender: mpsc::Sender<CommandRequest>) {
+        fn handle(
+            stream: UnixStream,
+            sender: &mpsc::Sender<CommandRequest>,
+        ) -> Result<(), io::Error> {
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line)?;
+
+            let result = match DaemonCommand::from_str(&line) {
+                Ok(command) => {
+                    let (reply_sender, reply_receiver) = mpsc::channel();
+                    match sender.send((command, reply_sender)) {
+                        Ok(_) => reply_receiver
+                            .recv()
+                            .unwrap_or(Err("The daemon stopped".to_owned())),
+                        Err(_) => Err("The daemon stopped".to_owned()),
+                    }
+                }
+                Err(err) => Err(err.to_string()),
+            };
+
+            let mut stream = stream;
+            match result {
+                Ok(message) => writeln!(stream, "ok {}", message),
+                Err(message) => writeln!(stream, "error {}", message),
+            }
+        }
+
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| handle(stream, &sender));
+
+            if let Err(err) = result {
+                println!("Failed to handle a daemon command: {}", err);
+            }
+        }
+    }
+
+    fn pick_image(source: &DaemonSource, state: &mut State) -> anyhow::Result<()> {
+        let fetch =
+            |category: Option<Category>, state: &mut State| -> anyhow::Result<()> {
+                // The effects are applied when the image is assigned, they are checked before fetching
+                let effects = category
+                    .as_ref()
+                    .map(|category| category.effects.clone())
+                    .unwrap_or_default();
+                Effects::from_list(&effects)?;
+                let parameters = SearchParameters::new(vec![], vec![], category);
+                let url_suppliers = UrlSupplier::find_many_in_config(&[])?;
+                let (search_result, url_supplier) = HTTPCLIENT
+                    .block_on(UrlSupplier::search_next(&url_suppliers, &parameters, state))?;
+                let source_url = search_result.get_url().to_string();
+                let image = IMAGECACHE.cache(
+                    &HTTPCLIENT.block_on(FetchedImage::fetch_from_url(search_result))?,
+                    &state.get_displayed_paths(),
+                )?;
+
+                HookContext {
+                    image: Some(image.get_absolute_path_as_string()?),
+                    supplier: Some(url_supplier.get_name().to_owned()),
+                    source_url: Some(source_url),
+                    ..Default::default()
+                }
+                .run_reporting(HookEvent::PostFetch);
+
+                state.set_current_image_with_effects(&image, effects, None)?;
+
+                Ok(())
+            };
+
+        match source {
+            DaemonSource::Collection { name } => {
+                let mut collection = Collection::open(name)?;
+                let image = collection
+                    .get_directory_mut()
+                    .get_random_image(None, None)?;
+                state.set_current_collection(&collection, &image, None)?;
+
+                Ok(())
+            }
+            DaemonSource::Category { name } => fetch(Some(Category::find_in_config(name)?), state),
+            DaemonSource::Fetch => fetch(None, state),
+        }
+    }
+
+    /// Picks a wallpaper from a random source and assigns it
+    fn change(&mut self) -> anyhow::Result<String> {
+        // Unwrap here, the sources are checked to not be empty on creation
+        let source = self.sources.choose(&mut rand::thread_rng()).unwrap();
+
+        let mut state = State::open()?;
+        Self::pick_image(source, &mut state)?;
+        state.assign_current_image()?;
+
+        // The state for all outputs is set above
+        let image_state = state.get_state(None).unwrap();
+
+        Ok(format!(
+            "Set wallpaper to image: {}",
+            image_state.get_image_path()
+        ))
+    }
+
+    fn previous(&self) -> anyhow::Result<String> {
+        let entry = State::open()?.go_back(1)?;
+
+        Ok(format!(
+            "Set wallpaper to image: {}",
+            entry.image.get_image_path()
+        ))
+    }
+
+    fn handle_command(&mut self, command: DaemonCommand) -> anyhow::Result<String> {
+        match command {
+            DaemonCommand::Next => self.change(),
+            DaemonCommand::Prev => self.previous(),
+            DaemonCommand::Pause => {
+                self.paused = !self.paused;
+
+                Ok(if self.paused { "Paused" } else { "Resumed" }.to_owned())
+            }
+        }
+    }
+
+    /// Runs the daemon, only returns on failure
+    pub fn run(mut self) -> Result<(), DaemonError> {
+        let listener = Self::bind_socket()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || Self::listen(listener, sender));
+
+        let mut next_change = self.schedule.get_next_change();
+
+        loop {
+            let request = match (self.paused, next_change) {
+                (false, Some(next_change)) => {
+                    receiver.recv_timeout(next_change.saturating_duration_since(Instant::now()))
+                }
+                _ => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match request {
+                Ok((command, reply_sender)) => {
+                    let result = self.handle_command(command).map_err(|err| err.to_string());
+                    // The client might have disconnected, which is fine
+                    let _ = reply_sender.send(result);
+
+                    // Reset the timer, seeing the wallpaper was changed manually, or the rotation was resumed
+                    // and shouldn't change right away because the pause outlasted the interval
+                    let is_resumed = command == DaemonCommand::Pause && !self.paused;
+                    if matches!(command, DaemonCommand::Next | DaemonCommand::Prev) || is_resumed {
+                        next_change = self.schedule.get_next_change();
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    match self.change() {
+                        Ok(message) => println!("{}", message),
+                        Err(err) => {
+                            println!("Failed to change the wallpaper: {:?}", err);
+                            hooks::report_error(&err.to_string());
+                        }
+                    }
+                    next_change = self.schedule.get_next_change();
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(DaemonError::FsError(io::Error::new(
+                        io::ErrorKind::BrokenPipe,
+                        "The control socket stopped",
+                    )))
+                }
+            }
+        }
+    }
+}
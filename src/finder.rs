@@ -25,17 +25,14 @@ where
 }
 
 pub fn check_string_equality(v1: &str, v2: &str) -> u32 {
-    v1.chars()
-        .into_iter()
-        .zip(v2.chars().into_iter())
-        .fold(
-            0u32,
-            |output, (v1, v2)| {
-                if v1 == v2 {
-                    output + 1
-                } else {
-                    output
-                }
-            },
-        )
+    v1.chars().zip(v2.chars()).fold(
+        0u32,
+        |output, (v1, v2)| {
+            if v1 == v2 {
+                output + 1
+            } else {
+                output
+            }
+        },
+    )
 }
@@ -0,0 +1,144 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{state::ImageStateType, BASEDIRECTORIES, CONFIG};
+
+/// The amount of entries kept if not specified in the config
+const DEFAULT_HISTORY_SIZE: usize = 50;
+
+#[derive(Debug, Error)]
+pub enum HistoryError {
+    #[error("An internal file system error occured: {0}")]
+    FsError(io::Error),
+    #[error("There is no history entry at index: {0}")]
+    EntryNotFound(usize),
+    #[error("The image of history entry {0} no longer exists")]
+    ImageMissing(usize),
+    #[error("There is no previous wallpaper in the history")]
+    NoPreviousEntry,
+}
+
+lazy_static::lazy_static! { static ref HISTORY_FILE: PathBuf = BASEDIRECTORIES.data_dir().join("history.toml"); }
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    pub image: ImageStateType,
+    /// The output the image was set for, none for all outputs
+    pub output: Option<String>,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+impl HistoryEntry {
+    pub fn is_missing(&self) -> bool {
+        !Path::new(self.image.get_image_path()).is_file()
+    }
+
+    /// The moment the image was set, formatted in local time
+    pub fn get_time(&self) -> String {
+        match chrono::DateTime::from_timestamp(self.timestamp as i64, 0) {
+            Some(time) => time
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            None => self.timestamp.to_string(),
+        }
+    }
+}
+
+/// The previously set wallpapers, the newest entry first
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct History {
+    #[serde(default)]
+    entries: Vec<HistoryEntry>,
+    /// The index of the entry currently set, moves when going back in history
+    #[serde(default)]
+    position: usize,
+    /// Whether the history has to be written back when it is dropped
+    #[serde(skip)]
+    changed: bool,
+}
+
+impl History {
+    pub fn open() -> Result<Self, HistoryError> {
+        let file_content = std::fs::read_to_string(HISTORY_FILE.clone());
+        match file_content {
+            Ok(file_content) => Ok(toml::from_str(&file_content).unwrap_or_default()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(HistoryError::FsError(err)),
+        }
+    }
+
+    /// Records a newly set image, dropping the oldest entries if the history is full
+    pub fn push(&mut self, image: ImageStateType, output: Option<&str>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        self.entries.insert(
+            0,
+            HistoryEntry {
+                image,
+                output: output.map(str::to_owned),
+                timestamp,
+            },
+        );
+        self.entries
+            .truncate(CONFIG.history_size.unwrap_or(DEFAULT_HISTORY_SIZE));
+        self.position = 0;
+        self.changed = true;
+    }
+
+    pub fn get_entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Get an entry which still has its image
+    pub fn get_entry(&self, index: usize) -> Result<&HistoryEntry, HistoryError> {
+        match self.entries.get(index) {
+            Some(entry) if entry.is_missing() => Err(HistoryError::ImageMissing(index)),
+            Some(entry) => Ok(entry),
+            None => Err(HistoryError::EntryNotFound(index)),
+        }
+    }
+
+    /// The index of the entry the amount of steps back from the position, skipping entries with missing images
+    pub fn get_previous_index(&self, steps: usize) -> Result<usize, HistoryError> {
+        self.entries
+            .iter()
+            .enumerate()
+            .skip(self.position + 1)
+            .filter(|(_, entry)| !entry.is_missing())
+            .nth(steps.saturating_sub(1))
+            .map(|(index, _)| index)
+            .ok_or(HistoryError::NoPreviousEntry)
+    }
+
+    pub fn get_position(&self) -> usize {
+        self.position
+    }
+
+    pub fn set_position(&mut self, position: usize) {
+        self.position = position;
+        self.changed = true;
+    }
+}
+
+impl Drop for History {
+    fn drop(&mut self) {
+        if !self.changed {
+            return;
+        }
+
+        let file_content = toml::to_string(&self).expect("Failed to serialize history to TOML");
+        std::fs::write(HISTORY_FILE.clone(), file_content)
+            .expect("Failed to write history to file.");
+    }
+}
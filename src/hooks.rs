@@ -0,0 +1,241 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use thiserror::Error;
+
+use crate::{
+    config::GlobalConfig,
+    image::{
+        palette::{Palette, DEFAULT_PALETTE_SIZE},
+        ImageError, SavedImage,
+    },
+    CONFIG,
+};
+
+/// The seconds a hook may run before it is stopped
+const DEFAULT_HOOK_TIMEOUT: u64 = 10;
+/// How often a running hook is checked for completion
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Error)]
+pub enum HookError {
+    #[error("An internal file system error occured: {0}")]
+    FsError(io::Error),
+    #[error("The hook: '{0}' failed: {1}")]
+    Failed(String, String),
+    #[error("The hook: '{0}' did not finish in time")]
+    TimedOut(String),
+    #[error("An internal image error occured: {0:?}")]
+    ImageError(ImageError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    PreSet,
+    PostSet,
+    PostFetch,
+    OnError,
+}
+
+impl HookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PreSet => "pre_set",
+            Self::PostSet => "post_set",
+            Self::PostFetch => "post_fetch",
+            Self::OnError => "on_error",
+        }
+    }
+
+    fn get_commands(&self) -> &'static [String] {
+        match self {
+            Self::PreSet => &CONFIG.hooks.pre_set,
+            Self::PostSet => &CONFIG.hooks.post_set,
+            Self::PostFetch => &CONFIG.hooks.post_fetch,
+            Self::OnError => &CONFIG.hooks.on_error,
+        }
+    }
+}
+
+/// What the hooks know about the image, passed as 'WALLTZ_' environment variables
+#[derive(Debug, Clone, Default)]
+pub struct HookContext {
+    pub image: Option<String>,
+    pub output: Option<String>,
+    pub collection: Option<String>,
+    pub supplier: Option<String>,
+    pub source_url: Option<String>,
+    pub error: Option<String>,
+}
+
+impl HookContext {
+    fn get_variables(&self, event: HookEvent) -> Vec<(&'static str, &str)> {
+        let variables = [
+            ("WALLTZ_IMAGE", &self.image),
+            ("WALLTZ_OUTPUT", &self.output),
+            ("WALLTZ_COLLECTION", &self.collection),
+            ("WALLTZ_SUPPLIER", &self.supplier),
+            ("WALLTZ_SOURCE_URL", &self.source_url),
+            ("WALLTZ_ERROR", &self.error),
+        ];
+
+        std::iter::once(("WALLTZ_EVENT", event.as_str()))
+            .chain(
+                variables
+                    .into_iter()
+                    .filter_map(|(name, value)| value.as_deref().map(|value| (name, value))),
+            )
+            .collect()
+    }
+
+    fn run_command(
+        &self,
+        event: HookEvent,
+        command: &str,
+        extra_variables: &[(String, String)],
+    ) -> Result<(), HookError> {
+        let timeout = Duration::from_secs(CONFIG.hooks.timeout.unwrap_or(DEFAULT_HOOK_TIMEOUT));
+
+        // A file instead of a pipe, so a hook writing a lot of errors can't block while being polled
+        fn spawn(command: &mut Command) -> Result<(std::process::Child, File), io::Error> {
+            let stderr = tempfile::tempfile()?;
+            let child = command.stderr(stderr.try_clone()?).spawn()?;
+
+            Ok((child, stderr))
+        }
+
+        let (mut child, mut stderr) = match spawn(
+            Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .envs(self.get_variables(event))
+                .envs(extra_variables.iter().map(|(name, value)| (name, value)))
+                .stdin(Stdio::null())
+                .stdout(Stdio::null()),
+        ) {
+            Ok(spawned) => spawned,
+            Err(err) => return Err(HookError::FsError(err)),
+        };
+
+        let started = Instant::now();
+        let status = loop {
+            match child.try_wait() {
+                Ok(Some(status)) => break status,
+                Ok(None) if started.elapsed() > timeout => {
+                    // The hook may have finished in the meantime, so failing to kill it is fine
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(HookError::TimedOut(command.to_owned()));
+                }
+                Ok(None) => thread::sleep(POLL_INTERVAL),
+                Err(err) => return Err(HookError::FsError(err)),
+            }
+        };
+
+        if status.success() {
+            return Ok(());
+        }
+
+        let mut message = String::new();
+        match stderr
+            .rewind()
+            .and_then(|_| stderr.read_to_string(&mut message))
+        {
+            Ok(_) => Err(HookError::Failed(
+                command.to_owned(),
+                message.trim().to_owned(),
+            )),
+            Err(err) => Err(HookError::FsError(err)),
+        }
+    }
+
+    /// Writes the templates of the post set hooks with the palette of the image, then runs their commands.
+    /// '{wallpaper}' and the colours of the palette are filled into the templates, the commands get them
+    /// as 'WALLTZ_WALLPAPER', 'WALLTZ_BACKGROUND', 'WALLTZ_FOREGROUND' and 'WALLTZ_COLOR0' up to 'WALLTZ_COLOR7'.
+    /// They are never pasted into a command, as the path partly comes from the suppliers.
+    fn run_post_set_hooks(&self) -> Result<(), HookError> {
+        if CONFIG.post_set_hooks.is_empty() {
+            return Ok(());
+        }
+        let Some(image) = &self.image else {
+            return Ok(());
+        };
+
+        let image = SavedImage::from_path(image).map_err(HookError::ImageError)?;
+        let wallpaper = image
+            .get_absolute_path_as_string()
+            .map_err(HookError::ImageError)?;
+        let palette =
+            Palette::from_image(&image, DEFAULT_PALETTE_SIZE).map_err(HookError::ImageError)?;
+        let fill = |template: &str| {
+            palette
+                .fill_template(template)
+                .replace("{wallpaper}", &wallpaper)
+        };
+        let variables = std::iter::once(("WALLPAPER".to_string(), wallpaper.clone()))
+            .chain(palette.get_variables())
+            .map(|(name, value)| (format!("WALLTZ_{}", name.to_ascii_uppercase()), value))
+            .collect::<Vec<_>>();
+
+        let config_path = GlobalConfig::get_config_path();
+        for hook in &CONFIG.post_set_hooks {
+            if let (Some(template), Some(template_output)) = (&hook.template, &hook.output) {
+                let template = match std::fs::read_to_string(config_path.join(template)) {
+                    Ok(template) => template,
+                    Err(err) => return Err(HookError::FsError(err)),
+                };
+
+                match std::fs::write(config_path.join(template_output), fill(&template)) {
+                    Ok(_) => {}
+                    Err(err) => return Err(HookError::FsError(err)),
+                }
+            }
+
+            if let Some(command) = &hook.command {
+                self.run_command(HookEvent::PostSet, command, &variables)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs the hooks of the event in order, stopping at the first failing hook.
+    /// The post set hooks with templates run before the plain post set commands.
+    pub fn run(&self, event: HookEvent) -> Result<(), HookError> {
+        if event == HookEvent::PostSet {
+            self.run_post_set_hooks()?;
+        }
+
+        for command in event.get_commands() {
+            self.run_command(event, command, &[])?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs the hooks of an event that can't stop anything, like post_set and post_fetch.
+    /// A failing hook is printed and passed to the error hooks instead of being returned.
+    pub fn run_reporting(&self, event: HookEvent) {
+        if let Err(err) = self.run(event) {
+            println!("Failed to run a {} hook: {}", event.as_str(), err);
+            report_error(&err.to_string());
+        }
+    }
+}
+
+/// Runs the error hooks for the message, failures of the hooks themselves are only printed
+pub fn report_error(message: &str) {
+    let context = HookContext {
+        error: Some(message.to_owned()),
+        ..Default::default()
+    };
+
+    if let Err(err) = context.run(HookEvent::OnError) {
+        println!("Failed to run an error hook: {}", err);
+    }
+}
@@ -1,36 +1,79 @@
 use std::{
-    fs::{self, metadata},
-    io::{self, BufReader, Cursor},
+    fs::{self},
+    io::{self, Read, Seek, Write},
     path::{Path, PathBuf},
     str::FromStr,
-    time::Duration,
 };
 
-use bytes::Bytes;
 use image::ImageFormat;
 
 pub mod cache;
+pub mod effects;
+pub mod palette;
+pub mod processing;
+pub mod similarity;
 pub mod url_supplier;
 
 use reqwest::Url;
 use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use similarity::PerceptualHash;
+use tempfile::NamedTempFile;
 use thiserror::Error;
-pub use url_supplier::UrlSupplier;
+pub use url_supplier::{SearchPosition, UrlSupplier};
 
-use crate::{BASEDIRECTORIES, IMAGECACHE};
+use crate::{
+    category::Category,
+    network::{NetworkError, DEFAULT_MAX_DOWNLOAD_SIZE},
+    CONFIG, HTTPCLIENT, IMAGECACHE,
+};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct SearchParameters {
     pub tags: Vec<String>,
+    /// Tags the images must not have
+    pub exclude_tags: Vec<String>,
     pub aspect_ratios: Vec<String>,
     /// Wether to skip images found in cache, if possible
     pub skip_cache: bool,
 }
 
+impl SearchParameters {
+    /// Parameters skipping cached images, with the tags and aspect ratios of the category or else the config.
+    pub fn new(tags: Vec<String>, exclude_tags: Vec<String>, category: Option<Category>) -> Self {
+        match category {
+            Some(category) => SearchParameters {
+                tags: tags.into_iter().chain(category.tags).collect(),
+                exclude_tags: exclude_tags
+                    .into_iter()
+                    .chain(category.exclude_tags)
+                    .collect(),
+                aspect_ratios: category.aspect_ratios,
+                skip_cache: true,
+            },
+            // TODO: Add aspect ratio arg in cli
+            None => SearchParameters {
+                tags,
+                exclude_tags: exclude_tags
+                    .into_iter()
+                    .chain(CONFIG.exclude_tags.iter().cloned())
+                    .collect(),
+                aspect_ratios: CONFIG.aspect_ratios.clone(),
+                skip_cache: true,
+            },
+        }
+    }
+}
+
+/// The amount of bytes read from the start of a download to detect its format
+const FORMAT_HEADER_SIZE: usize = 64;
+
 #[derive(Error, Debug)]
 pub enum ImageError {
     #[error("The image doesn't exist")]
     NotFound,
+    #[error("The image is not in the cache: {0}")]
+    NotCached(String),
     #[error("The format of the image is not supported, or not an image")]
     InvalidFormat,
     #[error("Cannot convert the image to the specified format")]
@@ -39,15 +82,30 @@ pub enum ImageError {
     FsError(io::Error),
     #[error("Failed to write image to file")]
     WriteFailed,
-    #[error("Failed to fetch image from url: {0:?}")]
-    FetchError(reqwest::Error),
+    #[error("Failed to fetch image from url: {0}")]
+    FetchError(NetworkError),
+    #[error("The image is larger than the maximum download size of {0} bytes")]
+    TooLarge(u64),
     #[error("The supplied url is invalid")]
     InvalidUrl,
     #[error("The supplied external image location is invalid")]
     InvalidExternal,
+    #[error("The image processing setting is invalid: {0}")]
+    InvalidProcessing(String),
+    #[error("The effect is invalid: {0}")]
+    InvalidEffect(String),
+}
+
+/// The sha256 hash of the content as hex, used to find identical images
+pub fn hash_content(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
 }
 
 /// An image on disk
+#[derive(Clone)]
 pub struct SavedImage {
     path: PathBuf,
     format: ImageFormat,
@@ -107,13 +165,35 @@ impl SavedImage {
         self.format
     }
 
+    pub fn get_size(&self) -> Result<u64, ImageError> {
+        match fs::metadata(&self.path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) => Err(ImageError::FsError(err)),
+        }
+    }
+
+    pub fn get_content_hash(&self) -> Result<String, ImageError> {
+        match fs::read(&self.path) {
+            Ok(data) => Ok(hash_content(&data)),
+            Err(err) => Err(ImageError::FsError(err)),
+        }
+    }
+
+    /// Decodes the image, SLOW
+    pub fn get_perceptual_hash(&self) -> Result<PerceptualHash, ImageError> {
+        match image::open(&self.path) {
+            Ok(image) => Ok(PerceptualHash::from_image(&image)),
+            Err(_) => Err(ImageError::InvalidFormat),
+        }
+    }
+
     pub fn copy_to<P>(&self, path: P) -> Result<SavedImage, ImageError>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
 
-        match ImageFormat::from_path(&path) {
+        match ImageFormat::from_path(path) {
             Ok(format) if format == self.format => {
                 fn inner(from_path: &Path, goal_path: &Path) -> Result<(), io::Error> {
                     let image_data = std::fs::read(from_path)?;
@@ -122,7 +202,7 @@ impl SavedImage {
                     Ok(())
                 }
 
-                inner(&self.path, path).map_err(|err| ImageError::FsError(err))?;
+                inner(&self.path, path).map_err(ImageError::FsError)?;
                 Ok(SavedImage::from_path(path)?)
             }
             Ok(_) => Err(ImageError::IncompatibleFormat),
@@ -133,16 +213,30 @@ impl SavedImage {
 
 enum FetchedImageType {
     Storage(SavedImage),
-    Memory(Bytes),
+    /// A finished download, the file is removed when the image is dropped
+    Download {
+        _file: NamedTempFile,
+        image: SavedImage,
+    },
 }
 
 pub struct FetchedImage {
     stem: String,
     data: FetchedImageType,
     format: ImageFormat,
+    /// Where the image was found, recorded in the cache index
+    origin: Option<ImageUrl>,
 }
 
 impl FetchedImage {
+    fn get_stored(&self) -> &SavedImage {
+        match &self.data {
+            FetchedImageType::Storage(saved) | FetchedImageType::Download { image: saved, .. } => {
+                saved
+            }
+        }
+    }
+
     /// Unlike save, this encodes the image correctly. SLOW
     pub fn save_to_format<P>(&self, path: P) -> Result<SavedImage, ImageError>
     where
@@ -164,26 +258,14 @@ impl FetchedImage {
             });
         }
 
-        let result = match &self.data {
-            FetchedImageType::Memory(bytes) => {
-                let reader =
-                    image::io::Reader::with_format(Cursor::new(bytes.clone()), self.format);
+        let reader = image::ImageReader::with_format(
+            io::BufReader::new(
+                fs::File::open(self.get_stored().get_path()).map_err(ImageError::FsError)?,
+            ),
+            self.format,
+        );
 
-                reader.decode().and_then(|image| image.save(path))
-            }
-            FetchedImageType::Storage(saved) => {
-                let reader = image::io::Reader::with_format(
-                    io::BufReader::new(
-                        fs::File::open(saved.get_path()).map_err(|err| ImageError::FsError(err))?,
-                    ),
-                    self.format,
-                );
-
-                reader.decode().and_then(|image| image.save(path))
-            }
-        };
-
-        match result {
+        match reader.decode().and_then(|image| image.save(path)) {
             Ok(_) => Ok(SavedImage {
                 path: path.to_owned(),
                 format: self.format,
@@ -195,7 +277,7 @@ impl FetchedImage {
     /// Just saves the file in it's pred
     pub fn save(&self, path: &Path) -> Result<SavedImage, ImageError> {
         match &self.data {
-            FetchedImageType::Memory(bytes) => {
+            FetchedImageType::Download { image, .. } => {
                 if !path.extension().is_some_and(|ext| {
                     self.format
                         .extensions_str()
@@ -204,8 +286,7 @@ impl FetchedImage {
                     return Err(ImageError::IncompatibleFormat);
                 }
 
-                let image_data = bytes.as_ref();
-                match std::fs::write(path, image_data) {
+                match std::fs::copy(image.get_path(), path) {
                     Ok(_) => Ok(SavedImage {
                         path: path.to_owned(),
                         format: self.format,
@@ -217,6 +298,19 @@ impl FetchedImage {
         }
     }
 
+    pub fn get_size(&self) -> Result<u64, ImageError> {
+        self.get_stored().get_size()
+    }
+
+    pub fn get_content_hash(&self) -> Result<String, ImageError> {
+        self.get_stored().get_content_hash()
+    }
+
+    /// Decodes the image, SLOW
+    pub fn get_perceptual_hash(&self) -> Result<PerceptualHash, ImageError> {
+        self.get_stored().get_perceptual_hash()
+    }
+
     pub fn get_file_extension(&self) -> &str {
         self.format.extensions_str().first().unwrap()
     }
@@ -226,47 +320,158 @@ impl FetchedImage {
     }
 
     /// Fetch the image from the url, or grab it out of cache if it already exists
-    pub fn fetch_from_url(image_url: ImageUrl) -> Result<Self, ImageError> {
-        if let Ok(cached_image) = IMAGECACHE.find(&image_url.stem) {
-            println!("Fetching from cache");
+    pub async fn fetch_from_url(image_url: ImageUrl) -> Result<Self, ImageError> {
+        Self::fetch_from_url_with_progress(image_url, |_, _| {}).await
+    }
+
+    /// The format of the downloaded file judged by its first bytes, which servers and urls can get wrong.
+    /// Formats without magic bytes are accepted if the file decodes as the expected format.
+    fn detect_format(
+        file: &mut NamedTempFile,
+        expected: ImageFormat,
+    ) -> Result<ImageFormat, ImageError> {
+        let mut header = Vec::with_capacity(FORMAT_HEADER_SIZE);
+        let result = file.rewind().and_then(|_| {
+            file.as_file()
+                .take(FORMAT_HEADER_SIZE as u64)
+                .read_to_end(&mut header)
+        });
+
+        if let Err(err) = result {
+            return Err(ImageError::FsError(err));
+        }
+
+        match image::guess_format(&header) {
+            Ok(format) => Ok(format),
+            Err(_) => {
+                let decoded = fs::File::open(file.path()).map(|opened| {
+                    image::ImageReader::with_format(io::BufReader::new(opened), expected).decode()
+                });
+
+                match decoded {
+                    Ok(Ok(_)) => Ok(expected),
+                    _ => Err(ImageError::InvalidFormat),
+                }
+            }
+        }
+    }
+
+    /// Streams the image from the url into the download directory of the cache, or grabs it out of cache if it already exists.
+    /// Reports the downloaded bytes and the total bytes if the server sends them.
+    pub async fn fetch_from_url_with_progress<F>(
+        image_url: ImageUrl,
+        on_progress: F,
+    ) -> Result<Self, ImageError>
+    where
+        F: Fn(u64, Option<u64>),
+    {
+        if let Some(cached_image) = IMAGECACHE.find_source(&IMAGECACHE.open_index()?, &image_url) {
             return Ok(Self {
-                stem: image_url.stem,
+                stem: image_url.stem.clone(),
                 format: cached_image.format,
                 data: FetchedImageType::Storage(cached_image),
+                origin: Some(image_url),
             });
         }
 
-        fn fetch_bytes(url: Url) -> Result<Bytes, reqwest::Error> {
-            let image_result = reqwest::blocking::get(url)?;
-            let image_bytes = image_result.bytes()?;
-
-            Ok(image_bytes)
+        let max_size = CONFIG
+            .network
+            .max_download_size
+            .unwrap_or(DEFAULT_MAX_DOWNLOAD_SIZE)
+            * 1024
+            * 1024;
+
+        let mut response = HTTPCLIENT
+            .get(image_url.url.clone())
+            .await
+            .map_err(ImageError::FetchError)?;
+
+        let total = response.content_length();
+        if total.is_some_and(|total| total > max_size) {
+            return Err(ImageError::TooLarge(max_size));
         }
 
-        match fetch_bytes(image_url.url) {
-            Ok(bytes) => Ok(FetchedImage {
-                stem: image_url.stem,
-                data: FetchedImageType::Memory(bytes),
-                format: image_url.image_format,
-            }),
-            Err(err) => Err(ImageError::FetchError(err)),
+        let mut file = IMAGECACHE.create_download_file()?;
+        let mut downloaded = 0;
+        on_progress(downloaded, total);
+
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|err| ImageError::FetchError(NetworkError::RequestError(err)))?
+        {
+            downloaded += chunk.len() as u64;
+            if downloaded > max_size {
+                return Err(ImageError::TooLarge(max_size));
+            }
+
+            match file.write_all(&chunk) {
+                Ok(_) => {}
+                Err(err) => return Err(ImageError::FsError(err)),
+            }
+            on_progress(downloaded, total);
         }
+
+        // Error pages served with a success status are no images
+        let format = Self::detect_format(&mut file, image_url.image_format)?;
+
+        Ok(FetchedImage {
+            stem: image_url.stem.clone(),
+            data: FetchedImageType::Download {
+                image: SavedImage {
+                    path: file.path().to_owned(),
+                    format,
+                },
+                _file: file,
+            },
+            format,
+            origin: Some(image_url),
+        })
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ImageUrl {
     stem: String,
     url: Url,
     image_format: ImageFormat,
+    /// The tags of the image, empty if the supplier doesn't provide them
+    tags: Vec<String>,
+    /// The supplier the image was found at
+    supplier: Option<String>,
+    /// The id of the image at the supplier, none if the supplier has no ids
+    remote_id: Option<String>,
 }
 
 impl ImageUrl {
+    pub fn get_url(&self) -> &Url {
+        &self.url
+    }
+
+    /// The id of the image at the supplier, used as the file name
+    pub fn get_stem(&self) -> &str {
+        &self.stem
+    }
+
+    pub fn get_remote_id(&self) -> Option<&str> {
+        self.remote_id.as_deref()
+    }
+
+    pub fn get_tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn has_any_tag(&self, tags: &[String]) -> bool {
+        self.tags
+            .iter()
+            .any(|tag| tags.iter().any(|other| tag.eq_ignore_ascii_case(other)))
+    }
+
     fn get_file_stem(url: &str) -> Result<String, ImageError> {
         match std::path::PathBuf::from_str(url) {
             Ok(path) => path
                 .file_stem()
-                .and_then(|file_name| Some(file_name.to_string_lossy().into_owned()))
+                .map(|file_name| file_name.to_string_lossy().into_owned())
                 .ok_or(ImageError::InvalidUrl),
             Err(_) => unreachable!(),
         }
@@ -282,16 +487,23 @@ impl ImageUrl {
     fn get_url_object(url: &str) -> Result<Url, ImageError> {
         Url::from_str(url).map_err(|_err| ImageError::InvalidUrl)
     }
+}
+
+impl FromStr for ImageUrl {
+    type Err = ImageError;
 
-    pub fn from_str(url: &str) -> Result<Self, ImageError> {
-        let stem = Self::get_file_stem(&url)?;
-        let image_format = Self::get_format(&url)?;
-        let url = Self::get_url_object(&url)?;
+    fn from_str(url: &str) -> Result<Self, Self::Err> {
+        let stem = Self::get_file_stem(url)?;
+        let image_format = Self::get_format(url)?;
+        let url = Self::get_url_object(url)?;
 
         Ok(Self {
             stem,
             image_format,
             url,
+            tags: vec![],
+            supplier: None,
+            remote_id: None,
         })
     }
 }
@@ -308,11 +520,13 @@ where
         Self { path }
     }
 
-    pub fn load(&self) -> Result<SavedImage, ImageError> {
+    /// Urls are downloaded into the cache, the kept paths survive the cleaning that makes room for them
+    pub fn load(&self, keep: &[PathBuf]) -> Result<SavedImage, ImageError> {
         match self.path.as_ref() {
             url if Url::from_str(url).is_ok_and(|v| ["https", "http"].contains(&v.scheme())) => {
-                let image = FetchedImage::fetch_from_url(ImageUrl::from_str(url)?)?;
-                IMAGECACHE.cache(&image)
+                let image =
+                    HTTPCLIENT.block_on(FetchedImage::fetch_from_url(ImageUrl::from_str(url)?))?;
+                IMAGECACHE.cache(&image, keep)
             }
             path if Path::new(path).is_file() => SavedImage::from_path(path),
             _ => Err(ImageError::InvalidExternal),
@@ -1,58 +1,417 @@
 use std::{
+    fs,
     path::{Path, PathBuf},
-    time::Duration,
+    time::UNIX_EPOCH,
 };
 
-use image::ImageFormat;
+use image::{DynamicImage, ImageFormat};
+use tempfile::NamedTempFile;
 
-use crate::BASEDIRECTORIES;
+use crate::{BASEDIRECTORIES, CONFIG};
 
-use super::{FetchedImage, ImageError, SavedImage};
+use super::{FetchedImage, ImageError, ImageUrl, SavedImage};
+
+pub mod index;
+
+use index::{get_timestamp, CacheEntry, CacheIndex, INDEX_FILE_NAME, INDEX_LOCK_FILE_NAME};
+
+/// The directory inside the cache holding processed variants of images
+const VARIANTS_DIRECTORY: &str = "variants";
+/// The directory inside the cache holding downloads in progress
+const DOWNLOADS_DIRECTORY: &str = "downloads";
+
+/// The days a cached file is kept after it was last used
+pub const DEFAULT_MAX_AGE: u64 = 7;
+
+/// A file in the cache, with when it was last used
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Seconds since the unix epoch
+    pub last_used: u64,
+    pub pinned: bool,
+}
+
+impl CachedFile {
+    pub fn get_last_used_time(&self) -> String {
+        match chrono::DateTime::from_timestamp(self.last_used as i64, 0) {
+            Some(time) => time
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            None => self.last_used.to_string(),
+        }
+    }
+}
+
+/// What a cleanup removed
+#[derive(Debug, Clone, Default)]
+pub struct CleanSummary {
+    pub removed: usize,
+    /// The bytes of the removed files
+    pub freed: u64,
+}
 
 /// A image cache manager, does cleanup next to saving and retrieving images.
 pub struct ImageCache;
 
 impl ImageCache {
-    pub fn cleanup_cache(&self) -> Result<(), ImageError> {
-        let files = match self.get_path().read_dir() {
-            Ok(files) => files,
-            Err(err) => return Err(ImageError::FsError(err)),
+    fn get_index_path(&self) -> PathBuf {
+        self.get_path().join(INDEX_FILE_NAME)
+    }
+
+    /// The path of the file relative to the cache directory, none if the file is not in the cache
+    fn get_key(&self, path: &Path) -> Option<String> {
+        let relative = match path.strip_prefix(self.get_path()) {
+            Ok(relative) => relative.to_owned(),
+            Err(_) => fs::canonicalize(path)
+                .ok()?
+                .strip_prefix(fs::canonicalize(self.get_path()).ok()?)
+                .ok()?
+                .to_owned(),
         };
 
-        fn should_cull(dir: std::fs::DirEntry) -> Option<PathBuf> {
-            let dir_path = dir.path();
+        Some(relative.to_string_lossy().into_owned())
+    }
 
-            let is_image = ImageFormat::from_path(&dir_path).is_ok();
-            if !is_image {
-                return None;
+    /// Locks the index against updates by other threads and processes until the file is dropped
+    fn lock_index(&self) -> Result<fs::File, ImageError> {
+        let result = fs::create_dir_all(self.get_path()).and_then(|_| {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(self.get_path().join(INDEX_LOCK_FILE_NAME))?;
+            file.lock()?;
+
+            Ok(file)
+        });
+
+        match result {
+            Ok(file) => Ok(file),
+            Err(err) => Err(ImageError::FsError(err)),
+        }
+    }
+
+    /// Opens the index, rebuilding it if it is lost. Only called while the index is locked.
+    fn load_index(&self) -> Result<CacheIndex, ImageError> {
+        match CacheIndex::open(&self.get_index_path())? {
+            Some(index) if index.is_complete() => Ok(index),
+            index => self.rescan(index.unwrap_or_default()),
+        }
+    }
+
+    /// Opens the index of the cache, rebuilding it from the cached files if it is lost
+    pub fn open_index(&self) -> Result<CacheIndex, ImageError> {
+        match CacheIndex::open(&self.get_index_path())? {
+            Some(index) if index.is_complete() => Ok(index),
+            // Rebuilt while locked, so an update in the meantime isn't overwritten
+            _ => {
+                let _lock = self.lock_index()?;
+                self.load_index()
             }
+        }
+    }
+
+    /// Changes the index and saves it, while no other thread or process can change it
+    fn update_index<F, T>(&self, update: F) -> Result<T, ImageError>
+    where
+        F: FnOnce(&mut CacheIndex) -> Result<T, ImageError>,
+    {
+        let _lock = self.lock_index()?;
+
+        let mut index = self.load_index()?;
+        let result = update(&mut index)?;
+        index.save(&self.get_index_path())?;
 
-            let last_modified = match dir.metadata().and_then(|metadata| metadata.modified()) {
-                Ok(modified) => modified,
-                Err(_) => return None,
+        Ok(result)
+    }
+
+    /// Indexes the cached files missing from the index or their hash, which are all files if the index was lost.
+    /// Where the files came from can't be recovered, so those files are only found by their content.
+    fn rescan(&self, mut index: CacheIndex) -> Result<CacheIndex, ImageError> {
+        for file in self.list_files(&index)? {
+            let Some(key) = self.get_key(&file.path) else {
+                continue;
             };
 
-            let elapsed_since_modified = match last_modified.elapsed() {
-                Ok(elapsed) => elapsed,
-                Err(_) => return None,
+            let mut entry = match index.get(&key) {
+                Some(entry) if entry.content_hash.is_some() => continue,
+                Some(entry) => entry.clone(),
+                None => CacheEntry {
+                    last_used: file.last_used,
+                    ..Default::default()
+                },
+            };
+            entry.content_hash = SavedImage::from_path(&file.path)
+                .and_then(|image| image.get_content_hash())
+                .ok();
+
+            index.insert(key, entry);
+        }
+
+        // Files that can't be hashed are left out, as the index would be rescanned on every open otherwise
+        index.retain(|key, entry| {
+            entry.content_hash.is_some() && self.get_path().join(key).is_file()
+        });
+        index.save(&self.get_index_path())?;
+
+        Ok(index)
+    }
+
+    /// The cached file for the key, none if the file is gone
+    fn load(&self, key: Option<&str>) -> Option<SavedImage> {
+        let path = self.get_path().join(key?);
+
+        if path.is_file() {
+            SavedImage::from_path(path).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Marks the image as used now and records where it came from, images outside of the cache are ignored
+    fn record(&self, image: &SavedImage, origin: Option<&ImageUrl>) -> Result<(), ImageError> {
+        let Some(key) = self.get_key(image.get_path()) else {
+            return Ok(());
+        };
+
+        self.update_index(|index| {
+            Self::add_to_index(index, &key, image)?;
+            index.touch(&key)?;
+            if let Some(origin) = origin {
+                index.add_source(&key, origin)?;
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Indexes the image with its hash, if it isn't indexed yet
+    fn add_to_index(
+        index: &mut CacheIndex,
+        key: &str,
+        image: &SavedImage,
+    ) -> Result<(), ImageError> {
+        if index.get(key).is_none() {
+            index.insert(
+                key.to_owned(),
+                CacheEntry {
+                    content_hash: Some(image.get_content_hash()?),
+                    ..Default::default()
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Marks the image as used now, images outside of the cache are ignored
+    pub fn touch(&self, image: &SavedImage) -> Result<(), ImageError> {
+        self.record(image, None)
+    }
+
+    /// The cached file downloaded for the image url, found by its supplier and id or else by its url
+    pub fn find_source(&self, index: &CacheIndex, image_url: &ImageUrl) -> Option<SavedImage> {
+        self.load(index.find_source(image_url))
+    }
+
+    /// Every image in the cache and its variants, the least recently used first
+    pub fn get_files(&self) -> Result<Vec<CachedFile>, ImageError> {
+        self.list_files(&self.open_index()?)
+    }
+
+    fn list_files(&self, index: &CacheIndex) -> Result<Vec<CachedFile>, ImageError> {
+        let mut files = vec![];
+
+        for directory in [self.get_path().to_owned(), self.get_variants_path()] {
+            if !directory.is_dir() {
+                continue;
+            }
+
+            let entries = match directory.read_dir() {
+                Ok(entries) => entries,
+                Err(err) => return Err(ImageError::FsError(err)),
             };
 
-            // 7 days before deletion
-            // TODO: Make this a value in the config
-            const DELETION_THRESHOLD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+            for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+                if !path.is_file() || ImageFormat::from_path(&path).is_err() {
+                    continue;
+                }
+
+                let metadata = match path.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(err) => return Err(ImageError::FsError(err)),
+                };
+
+                // Files cached before they were indexed count as used when they were last modified
+                let entry = self.get_key(&path).and_then(|key| index.get(&key));
+                let last_used = match entry {
+                    Some(entry) => entry.last_used,
+                    None => metadata
+                        .modified()
+                        .ok()
+                        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                        .map(|duration| duration.as_secs())
+                        .unwrap_or_default(),
+                };
 
-            if elapsed_since_modified > DELETION_THRESHOLD {
-                Some(dir_path)
-            } else {
-                None
+                files.push(CachedFile {
+                    path,
+                    size: metadata.len(),
+                    last_used,
+                    pinned: entry.is_some_and(|entry| entry.pinned),
+                });
             }
         }
 
-        for file_path in files.filter_map(|dir| dir.ok().and_then(should_cull)) {
-            match std::fs::remove_file(file_path) {
+        files.sort_by_key(|file| file.last_used);
+        Ok(files)
+    }
+
+    /// A cached image by its file name or stem, variants included
+    pub fn find(&self, name: &str) -> Result<SavedImage, ImageError> {
+        let file = self.get_files()?.into_iter().find(|file| {
+            [file.path.file_name(), file.path.file_stem()]
+                .into_iter()
+                .flatten()
+                .any(|file_name| file_name == name)
+        });
+
+        match file {
+            Some(file) => SavedImage::from_path(file.path),
+            None => Err(ImageError::NotCached(name.to_owned())),
+        }
+    }
+
+    /// What the index knows about the cached image
+    pub fn get_entry(&self, image: &SavedImage) -> Result<CacheEntry, ImageError> {
+        let key = self.get_cached_key(image)?;
+        let entry = self.open_index()?.get(&key).cloned();
+
+        Ok(entry.unwrap_or_default())
+    }
+
+    /// The key of the image, which must be a file in the cache
+    fn get_cached_key(&self, image: &SavedImage) -> Result<String, ImageError> {
+        match self.get_key(image.get_path()) {
+            Some(key) if image.get_path().is_file() => Ok(key),
+            _ => Err(ImageError::NotCached(
+                image.get_path().to_string_lossy().into_owned(),
+            )),
+        }
+    }
+
+    /// Pinned images are kept by every cleanup until they are unpinned
+    pub fn set_pinned(&self, image: &SavedImage, pinned: bool) -> Result<(), ImageError> {
+        let key = self.get_cached_key(image)?;
+
+        // Files left out of the index because they couldn't be hashed can't be pinned either
+        self.update_index(|index| {
+            Self::add_to_index(index, &key, image)?;
+            index.set_pinned(&key, pinned)
+        })
+    }
+
+    /// Forgets an image that was moved out of the cache
+    pub fn forget(&self, image: &SavedImage) -> Result<(), ImageError> {
+        let Some(key) = self.get_key(image.get_path()) else {
+            return Ok(());
+        };
+
+        self.update_index(|index| {
+            index.remove(&key);
+            Ok(())
+        })
+    }
+
+    /// Removes the files for which the predicate holds, given the file count and total size left.
+    /// Pinned files and the kept paths, like the images the state displays, are never removed.
+    /// Pinned files don't count towards the count and size either.
+    fn remove_files<F>(
+        &self,
+        keep: &[PathBuf],
+        should_remove: F,
+    ) -> Result<CleanSummary, ImageError>
+    where
+        F: Fn(&CachedFile, usize, u64) -> bool,
+    {
+        let protected: Vec<PathBuf> = keep
+            .iter()
+            .filter_map(|path| fs::canonicalize(path).ok())
+            .collect();
+
+        let files: Vec<CachedFile> = self
+            .get_files()?
+            .into_iter()
+            .filter(|file| !file.pinned)
+            .collect();
+        let mut count = files.len();
+        let mut size: u64 = files.iter().map(|file| file.size).sum();
+        let mut summary = CleanSummary::default();
+
+        for file in files {
+            if !should_remove(&file, count, size)
+                || fs::canonicalize(&file.path).is_ok_and(|path| protected.contains(&path))
+            {
+                continue;
+            }
+
+            match fs::remove_file(&file.path) {
                 Ok(_) => {}
                 Err(err) => return Err(ImageError::FsError(err)),
             }
+
+            count -= 1;
+            size -= file.size;
+            summary.removed += 1;
+            summary.freed += file.size;
+        }
+
+        // Forgets the removed files, and files removed outside of walltz
+        self.update_index(|index| {
+            index.retain(|key, _| self.get_path().join(key).is_file());
+            Ok(())
+        })?;
+
+        Ok(summary)
+    }
+
+    /// Removes files past the limits of the config, the least recently used first
+    pub fn clean(&self, keep: &[PathBuf]) -> Result<CleanSummary, ImageError> {
+        let config = &CONFIG.cache;
+        let max_age = config.max_age.unwrap_or(DEFAULT_MAX_AGE) * 24 * 60 * 60;
+        let max_size = config.max_size.map(|max_size| max_size * 1024 * 1024);
+        let now = get_timestamp();
+
+        self.remove_files(keep, |file, count, size| {
+            now.saturating_sub(file.last_used) > max_age
+                || max_size.is_some_and(|max_size| size > max_size)
+                || config.max_count.is_some_and(|max_count| count > max_count)
+        })
+    }
+
+    /// Removes every cached file, except pinned files and the kept paths
+    pub fn clear(&self, keep: &[PathBuf]) -> Result<CleanSummary, ImageError> {
+        self.remove_files(keep, |_, _, _| true)
+    }
+
+    /// Records a newly stored image and cleans up to make room for it
+    fn add(
+        &self,
+        image: &SavedImage,
+        origin: Option<&ImageUrl>,
+        keep: &[PathBuf],
+    ) -> Result<(), ImageError> {
+        self.record(image, origin)?;
+
+        let mut keep = keep.to_vec();
+        keep.push(image.get_path().to_path_buf());
+
+        // A cache that can't be cleaned still works, so this doesn't fail storing the image
+        if let Err(err) = self.clean(&keep) {
+            println!("Failed to clean the cache: {}", err);
         }
 
         Ok(())
@@ -62,38 +421,137 @@ impl ImageCache {
         BASEDIRECTORIES.cache_dir()
     }
 
-    pub fn find(&self, name: &str) -> Result<SavedImage, ImageError> {
-        let mut files = match self.get_path().read_dir() {
-            Ok(direntries) => direntries,
-            Err(err) => return Err(ImageError::FsError(err)),
-        };
+    pub fn get_variants_path(&self) -> PathBuf {
+        self.get_path().join(VARIANTS_DIRECTORY)
+    }
 
-        let file = files.find_map(|direntry| match direntry {
-            Ok(direntry) => {
-                let file_path = direntry.path();
-                let stem = match file_path.file_stem() {
-                    Some(stem) => stem.to_string_lossy(),
-                    None => return None,
-                };
+    /// A file for a download in progress, removed when it is dropped
+    pub fn create_download_file(&self) -> Result<NamedTempFile, ImageError> {
+        let downloads_path = self.get_path().join(DOWNLOADS_DIRECTORY);
 
-                if stem == name {
-                    Some(file_path)
-                } else {
-                    None
-                }
-            }
-            Err(_) => None,
+        let result = std::fs::create_dir_all(&downloads_path).and_then(|_| {
+            tempfile::Builder::new()
+                .prefix("download-")
+                .tempfile_in(&downloads_path)
         });
 
-        match file {
-            Some(file_path) => SavedImage::from_path(file_path),
-            None => Err(ImageError::NotFound),
+        match result {
+            Ok(file) => Ok(file),
+            Err(err) => Err(ImageError::FsError(err)),
         }
     }
 
-    pub fn cache(&self, image: &FetchedImage) -> Result<SavedImage, ImageError> {
+    /// Where the variant of the image for the processing key is stored, keyed by the image content
+    pub fn get_variant_path(
+        &self,
+        image: &SavedImage,
+        key: &str,
+        format: ImageFormat,
+    ) -> Result<PathBuf, ImageError> {
+        let variants_path = self.get_variants_path();
+        if !variants_path.is_dir() {
+            match std::fs::create_dir_all(&variants_path) {
+                Ok(_) => {}
+                Err(err) => return Err(ImageError::FsError(err)),
+            }
+        }
+
+        let content_hash = image.get_content_hash()?;
+
+        Ok(variants_path.join(format!(
+            "{}-{}.{}",
+            &content_hash[..16],
+            key,
+            format.extensions_str().first().unwrap_or(&"png")
+        )))
+    }
+
+    /// The format variants of the image are written in.
+    /// Jpeg stays jpeg, everything else is written as png as not every format can be encoded.
+    fn get_variant_format(image: &SavedImage) -> ImageFormat {
+        match image.get_format() {
+            ImageFormat::Jpeg => ImageFormat::Jpeg,
+            _ => ImageFormat::Png,
+        }
+    }
+
+    /// The variant of the image for the processing key, if it was made already
+    pub fn find_variant(
+        &self,
+        image: &SavedImage,
+        key: &str,
+    ) -> Result<Option<SavedImage>, ImageError> {
+        let variant_path = self.get_variant_path(image, key, Self::get_variant_format(image))?;
+
+        match variant_path.is_file() {
+            true => Ok(Some(SavedImage::from_path(variant_path)?)),
+            false => Ok(None),
+        }
+    }
+
+    /// The variant of the image made by the transform, which is only run if the variant is not cached yet
+    pub fn get_or_create_variant<F>(
+        &self,
+        image: &SavedImage,
+        key: &str,
+        transform: F,
+    ) -> Result<SavedImage, ImageError>
+    where
+        F: FnOnce(DynamicImage) -> DynamicImage,
+    {
+        if let Some(variant) = self.find_variant(image, key)? {
+            self.touch(&variant)?;
+
+            return Ok(variant);
+        }
+
+        let format = Self::get_variant_format(image);
+        let variant_path = self.get_variant_path(image, key, format)?;
+
+        let decoded = match image::open(image.get_path()) {
+            Ok(decoded) => decoded,
+            Err(_) => return Err(ImageError::InvalidFormat),
+        };
+
+        let variant = match format {
+            // Jpeg has no alpha channel
+            ImageFormat::Jpeg => DynamicImage::ImageRgb8(transform(decoded).to_rgb8()),
+            _ => transform(decoded),
+        };
+
+        let variant = match variant.save_with_format(&variant_path, format) {
+            Ok(_) => SavedImage::from_path(variant_path)?,
+            Err(_) => return Err(ImageError::WriteFailed),
+        };
+        // Variants are made while assigning, before the state knows about them, so this doesn't clean.
+        // The cache is cleaned the next time an image is stored.
+        self.record(&variant, None)?;
+
+        Ok(variant)
+    }
+
+    /// Find a cached image with the exact same content
+    pub fn find_identical(&self, image: &FetchedImage) -> Result<Option<SavedImage>, ImageError> {
+        let index = self.open_index()?;
+        let content_hash = image.get_content_hash()?;
+
+        Ok(self.load(index.find_content_hash(&content_hash)))
+    }
+
+    /// Saves the image in the cache, unless an identical image is cached already.
+    /// The kept paths, like the images the state displays, survive the cleaning that makes room for it.
+    pub fn cache(&self, image: &FetchedImage, keep: &[PathBuf]) -> Result<SavedImage, ImageError> {
+        if let Some(cached_image) = self.find_identical(image)? {
+            self.record(&cached_image, image.origin.as_ref())?;
+
+            return Ok(cached_image);
+        }
+
         let file_name = image.get_file_name();
         let file_path = self.get_path().join(file_name);
-        image.save(&file_path)
+        let cached_image = image.save(&file_path)?;
+        self.add(&cached_image, image.origin.as_ref(), keep)?;
+
+        Ok(cached_image)
     }
 }
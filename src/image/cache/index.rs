@@ -0,0 +1,214 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{self, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tempfile::NamedTempFile;
+
+use crate::image::{ImageError, ImageUrl};
+
+/// The name of the index file inside the cache directory
+pub const INDEX_FILE_NAME: &str = "index.toml";
+/// The name of the file locked while the index is updated
+pub const INDEX_LOCK_FILE_NAME: &str = "index.lock";
+
+/// Seconds since the unix epoch
+pub fn get_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CacheEntry {
+    /// Seconds since the unix epoch
+    pub last_used: u64,
+    /// The supplier the image was found at
+    pub supplier: Option<String>,
+    /// The id of the image at the supplier
+    pub remote_id: Option<String>,
+    /// The url the image was downloaded from
+    pub source_url: Option<String>,
+    /// The tags given by the supplier
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Pinned files are never removed by a cleanup
+    #[serde(default)]
+    pub pinned: bool,
+    /// The sha256 hash of the file, used to find identical images
+    pub content_hash: Option<String>,
+}
+
+/// What is known about the cached files, keyed by their path relative to the cache directory
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct CacheIndex {
+    #[serde(default)]
+    entries: BTreeMap<String, CacheEntry>,
+    /// The key for every supplier and remote id
+    #[serde(skip)]
+    by_remote_id: HashMap<(String, String), String>,
+    /// The key for every source url
+    #[serde(skip)]
+    by_source_url: HashMap<String, String>,
+    /// The key for every content hash
+    #[serde(skip)]
+    by_content_hash: HashMap<String, String>,
+}
+
+impl CacheIndex {
+    /// Opens the index, none if there is no readable index
+    pub fn open(path: &Path) -> Result<Option<Self>, ImageError> {
+        let file_content = match std::fs::read_to_string(path) {
+            Ok(file_content) => file_content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(ImageError::FsError(err)),
+        };
+
+        Ok(toml::from_str::<Self>(&file_content).ok().map(|mut index| {
+            index.build_lookups();
+            index
+        }))
+    }
+
+    /// Writes the index to a temporary file first, so a reader never sees a half written index
+    pub fn save(&self, path: &Path) -> Result<(), ImageError> {
+        let file_content = match toml::to_string(self) {
+            Ok(file_content) => file_content,
+            Err(_) => return Err(ImageError::WriteFailed),
+        };
+
+        let directory = path.parent().unwrap_or(Path::new("."));
+        let result = NamedTempFile::new_in(directory).and_then(|mut file| {
+            file.write_all(file_content.as_bytes())?;
+            file.persist(path).map_err(|err| err.error)?;
+
+            Ok(())
+        });
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) => Err(ImageError::FsError(err)),
+        }
+    }
+
+    fn add_lookups(&mut self, key: &str) {
+        let Some(entry) = self.entries.get(key) else {
+            return;
+        };
+
+        if let (Some(supplier), Some(remote_id)) = (&entry.supplier, &entry.remote_id) {
+            self.by_remote_id
+                .insert((supplier.clone(), remote_id.clone()), key.to_owned());
+        }
+        if let Some(source_url) = &entry.source_url {
+            self.by_source_url
+                .insert(source_url.clone(), key.to_owned());
+        }
+        if let Some(content_hash) = &entry.content_hash {
+            self.by_content_hash
+                .insert(content_hash.clone(), key.to_owned());
+        }
+    }
+
+    fn build_lookups(&mut self) {
+        self.by_remote_id.clear();
+        self.by_source_url.clear();
+        self.by_content_hash.clear();
+
+        let keys: Vec<String> = self.entries.keys().cloned().collect();
+        for key in keys {
+            self.add_lookups(&key);
+        }
+    }
+
+    /// Wether every file has its content hash, the cache is rescanned otherwise
+    pub fn is_complete(&self) -> bool {
+        self.entries
+            .values()
+            .all(|entry| entry.content_hash.is_some())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&CacheEntry> {
+        self.entries.get(key)
+    }
+
+    pub fn get_entries(&self) -> impl Iterator<Item = (&String, &CacheEntry)> {
+        self.entries.iter()
+    }
+
+    /// Stores the entry, replacing what was known about the file
+    pub fn insert(&mut self, key: String, entry: CacheEntry) {
+        self.entries.insert(key.clone(), entry);
+        self.add_lookups(&key);
+    }
+
+    /// The entry of an indexed file.
+    /// Entries are only created by inserting them with their hash, an entry without one would make the cache rescan.
+    fn get_indexed_mut(&mut self, key: &str) -> Result<&mut CacheEntry, ImageError> {
+        self.entries
+            .get_mut(key)
+            .ok_or_else(|| ImageError::NotCached(key.to_owned()))
+    }
+
+    /// Marks the file as used now
+    pub fn touch(&mut self, key: &str) -> Result<(), ImageError> {
+        self.get_indexed_mut(key)?.last_used = get_timestamp();
+        Ok(())
+    }
+
+    /// Records where the file came from, so it is found for the image url the next time
+    pub fn add_source(&mut self, key: &str, image_url: &ImageUrl) -> Result<(), ImageError> {
+        let entry = self.get_indexed_mut(key)?;
+        entry.supplier = image_url.supplier.clone();
+        entry.remote_id = image_url.remote_id.clone();
+        entry.source_url = Some(image_url.url.to_string());
+        entry.tags = image_url.tags.clone();
+
+        self.add_lookups(key);
+        Ok(())
+    }
+
+    pub fn set_pinned(&mut self, key: &str, pinned: bool) -> Result<(), ImageError> {
+        self.get_indexed_mut(key)?.pinned = pinned;
+        Ok(())
+    }
+
+    /// Forgets the file, like when it was moved out of the cache
+    pub fn remove(&mut self, key: &str) {
+        if self.entries.remove(key).is_some() {
+            self.build_lookups();
+        }
+    }
+
+    /// The key of the file downloaded for the image url, by its supplier and id or else by its url
+    pub fn find_source(&self, image_url: &ImageUrl) -> Option<&str> {
+        let by_remote_id = match (&image_url.supplier, &image_url.remote_id) {
+            (Some(supplier), Some(remote_id)) => self
+                .by_remote_id
+                .get(&(supplier.clone(), remote_id.clone())),
+            _ => None,
+        };
+
+        by_remote_id
+            .or_else(|| self.by_source_url.get(image_url.url.as_str()))
+            .map(String::as_str)
+    }
+
+    /// The key of the file with the content hash
+    pub fn find_content_hash(&self, content_hash: &str) -> Option<&str> {
+        self.by_content_hash.get(content_hash).map(String::as_str)
+    }
+
+    /// Forgets the files for which the predicate is false, like files removed outside of walltz
+    pub fn retain<F>(&mut self, predicate: F)
+    where
+        F: Fn(&str, &CacheEntry) -> bool,
+    {
+        self.entries.retain(|key, entry| predicate(key, entry));
+        self.build_lookups();
+    }
+}
@@ -0,0 +1,235 @@
+use std::{fmt::Display, str::FromStr};
+
+use image::DynamicImage;
+
+use crate::IMAGECACHE;
+
+use super::{processing::parse_colour, ImageError, SavedImage};
+
+/// A change to the look of an image, written as 'name=value'
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    /// A gaussian blur with the sigma
+    Blur(f32),
+    /// Darkens the image, from 0 (unchanged) to 1 (black)
+    Dim(f32),
+    Grayscale,
+    /// Multiplies the image with the colour
+    Tint([u8; 3]),
+}
+
+impl Effect {
+    fn apply(&self, image: DynamicImage) -> DynamicImage {
+        match self {
+            Self::Blur(sigma) => image.blur(*sigma),
+            Self::Dim(amount) => {
+                let factor = 1.0 - amount;
+                let mut pixels = image.into_rgba8();
+                for pixel in pixels.pixels_mut() {
+                    for channel in pixel.0.iter_mut().take(3) {
+                        *channel = (*channel as f32 * factor) as u8;
+                    }
+                }
+
+                DynamicImage::ImageRgba8(pixels)
+            }
+            Self::Grayscale => image.grayscale(),
+            Self::Tint(colour) => {
+                let mut pixels = image.into_rgba8();
+                for pixel in pixels.pixels_mut() {
+                    for (channel, tint) in pixel.0.iter_mut().zip(colour) {
+                        *channel = (*channel as u16 * *tint as u16 / 255) as u8;
+                    }
+                }
+
+                DynamicImage::ImageRgba8(pixels)
+            }
+        }
+    }
+}
+
+impl FromStr for Effect {
+    type Err = ImageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ImageError::InvalidEffect(s.to_owned());
+
+        let (name, value) = match s.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (s.trim(), None),
+        };
+
+        match (name.to_ascii_lowercase().as_str(), value) {
+            ("blur", Some(value)) => match value.parse::<f32>() {
+                Ok(sigma) if sigma > 0.0 => Ok(Self::Blur(sigma)),
+                _ => Err(invalid()),
+            },
+            ("dim", Some(value)) => match value.parse::<f32>() {
+                Ok(amount) if (0.0..=1.0).contains(&amount) => Ok(Self::Dim(amount)),
+                _ => Err(invalid()),
+            },
+            ("grayscale" | "greyscale", None) => Ok(Self::Grayscale),
+            ("tint", Some(value)) => parse_colour(value).map(Self::Tint).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Display for Effect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Blur(sigma) => write!(f, "blur={}", sigma),
+            Self::Dim(amount) => write!(f, "dim={}", amount),
+            Self::Grayscale => write!(f, "grayscale"),
+            Self::Tint([red, green, blue]) => {
+                write!(f, "tint={:02x}{:02x}{:02x}", red, green, blue)
+            }
+        }
+    }
+}
+
+/// Effects applied in order, parsed from a comma separated list like 'blur=12,dim=0.4'
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Effects(Vec<Effect>);
+
+impl Effects {
+    /// Parses a list of effects, of which every entry may hold multiple effects
+    pub fn from_list(list: &[String]) -> Result<Self, ImageError> {
+        list.join(",").parse()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Describes the effects, used to tell variants of the same image apart
+    fn get_key(&self) -> String {
+        format!("effects-{}", self)
+    }
+
+    /// The image with the effects applied, if it was made already
+    pub fn find_applied_to(&self, image: SavedImage) -> Result<Option<SavedImage>, ImageError> {
+        if self.is_empty() {
+            return Ok(Some(image));
+        }
+
+        IMAGECACHE.find_variant(&image, &self.get_key())
+    }
+
+    /// Applies the effects to the image, reusing the cached result if it exists
+    pub fn apply_to(&self, image: &SavedImage) -> Result<SavedImage, ImageError> {
+        if self.is_empty() {
+            return SavedImage::from_path(image.get_path());
+        }
+
+        IMAGECACHE.get_or_create_variant(image, &self.get_key(), |decoded| {
+            self.0
+                .iter()
+                .fold(decoded, |image, effect| effect.apply(image))
+        })
+    }
+}
+
+impl FromStr for Effects {
+    type Err = ImageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .filter(|effect| !effect.trim().is_empty())
+            .map(Effect::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map(Self)
+    }
+}
+
+impl Display for Effects {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let effects = self.0.iter().map(Effect::to_string).collect::<Vec<_>>();
+
+        write!(f, "{}", effects.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(effects: &str) -> Result<Vec<Effect>, ImageError> {
+        effects.parse::<Effects>().map(|effects| effects.0)
+    }
+
+    #[test]
+    fn parses_every_effect() {
+        assert_eq!(parse("blur=12").unwrap(), [Effect::Blur(12.0)]);
+        assert_eq!(parse("dim=0.4").unwrap(), [Effect::Dim(0.4)]);
+        assert_eq!(parse("grayscale").unwrap(), [Effect::Grayscale]);
+        assert_eq!(parse("greyscale").unwrap(), [Effect::Grayscale]);
+        assert_eq!(
+            parse("tint=#ff8000").unwrap(),
+            [Effect::Tint([255, 128, 0])]
+        );
+        assert_eq!(parse("tint=FF8000").unwrap(), [Effect::Tint([255, 128, 0])]);
+    }
+
+    #[test]
+    fn parses_lists_in_order() {
+        assert_eq!(
+            parse(" Blur = 2.5 , dim=1,grayscale ").unwrap(),
+            [Effect::Blur(2.5), Effect::Dim(1.0), Effect::Grayscale]
+        );
+
+        let list = ["blur=1".to_owned(), "dim=0,tint=#000000".to_owned()];
+        assert_eq!(
+            Effects::from_list(&list).unwrap().0,
+            [Effect::Blur(1.0), Effect::Dim(0.0), Effect::Tint([0, 0, 0])]
+        );
+    }
+
+    #[test]
+    fn empty_input_has_no_effects() {
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse(" , ,").unwrap().is_empty());
+        assert!(Effects::from_list(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_values() {
+        for effect in [
+            "blur",
+            "blur=0",
+            "blur=-1",
+            "blur=strong",
+            "dim",
+            "dim=1.5",
+            "dim=-0.1",
+            "grayscale=1",
+            "tint",
+            "tint=#fff",
+            "tint=#gggggg",
+            "sepia",
+            "=1",
+        ] {
+            assert!(
+                matches!(parse(effect), Err(ImageError::InvalidEffect(_))),
+                "{} should not parse",
+                effect
+            );
+        }
+    }
+
+    #[test]
+    fn a_bad_effect_fails_the_list() {
+        assert!(parse("blur=2,sepia").is_err());
+    }
+
+    #[test]
+    fn displays_as_parsed() {
+        let effects: Effects = "blur=2.5,dim=0.4,grayscale,tint=#ff8000".parse().unwrap();
+
+        assert_eq!(
+            effects.to_string(),
+            "blur=2.5,dim=0.4,grayscale,tint=ff8000"
+        );
+        assert_eq!(effects.to_string().parse::<Effects>().unwrap(), effects);
+    }
+}
@@ -0,0 +1,189 @@
+use image::imageops::FilterType;
+
+use super::{ImageError, SavedImage};
+
+/// The amount of colours extracted from an image
+pub const DEFAULT_PALETTE_SIZE: usize = 8;
+/// The longest side images are shrunk to before extracting colours
+const SAMPLE_SIZE: u32 = 128;
+
+/// How a palette is written out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PaletteFormat {
+    #[default]
+    Json,
+    Css,
+    Xresources,
+    Kitty,
+}
+
+/// The dominant colours of an image, ordered from dark to light
+#[derive(Debug, Clone)]
+pub struct Palette {
+    colours: Vec<[u8; 3]>,
+}
+
+fn to_hex([red, green, blue]: [u8; 3]) -> String {
+    format!("#{:02x}{:02x}{:02x}", red, green, blue)
+}
+
+fn get_luminance([red, green, blue]: &[u8; 3]) -> u32 {
+    // Rec. 601 weights, scaled to stay in integers
+    *red as u32 * 299 + *green as u32 * 587 + *blue as u32 * 114
+}
+
+/// The channel with the largest spread of values in the bucket, and that spread
+fn get_widest_channel(bucket: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = bucket.iter().map(|pixel| pixel[channel]);
+            let spread =
+                values.clone().max().unwrap_or_default() - values.min().unwrap_or_default();
+
+            (channel, spread)
+        })
+        .max_by_key(|(_, spread)| *spread)
+        .unwrap_or_default()
+}
+
+fn get_average(bucket: &[[u8; 3]]) -> [u8; 3] {
+    let mut sums = [0u64; 3];
+    for pixel in bucket {
+        for (sum, value) in sums.iter_mut().zip(pixel) {
+            *sum += *value as u64;
+        }
+    }
+
+    sums.map(|sum| (sum / bucket.len() as u64) as u8)
+}
+
+impl Palette {
+    /// Extracts the colours with median cut, repeatedly halving the bucket with the widest colour range
+    pub fn from_pixels(pixels: Vec<[u8; 3]>, size: usize) -> Self {
+        let mut buckets = vec![pixels];
+
+        while buckets.len() < size {
+            let widest = buckets
+                .iter()
+                .enumerate()
+                .filter(|(_, bucket)| bucket.len() > 1)
+                .map(|(index, bucket)| (index, get_widest_channel(bucket)))
+                .max_by_key(|(_, (_, spread))| *spread);
+
+            let (index, channel) = match widest {
+                Some((index, (channel, spread))) if spread > 0 => (index, channel),
+                // Every bucket holds a single colour
+                _ => break,
+            };
+
+            let mut bucket = buckets.swap_remove(index);
+            bucket.sort_unstable_by_key(|pixel| pixel[channel]);
+            let upper = bucket.split_off(bucket.len() / 2);
+
+            buckets.push(bucket);
+            buckets.push(upper);
+        }
+
+        let mut colours: Vec<[u8; 3]> = buckets
+            .iter()
+            .filter(|bucket| !bucket.is_empty())
+            .map(|bucket| get_average(bucket))
+            .collect();
+        colours.sort_by_key(get_luminance);
+
+        Self { colours }
+    }
+
+    pub fn from_image(image: &SavedImage, size: usize) -> Result<Self, ImageError> {
+        let decoded = match image::open(image.get_path()) {
+            Ok(decoded) => decoded,
+            Err(_) => return Err(ImageError::InvalidFormat),
+        };
+
+        let pixels = decoded
+            .resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Triangle)
+            .into_rgb8()
+            .pixels()
+            .map(|pixel| pixel.0)
+            .collect();
+
+        Ok(Self::from_pixels(pixels, size))
+    }
+
+    /// The darkest colour
+    pub fn get_background(&self) -> String {
+        self.colours
+            .first()
+            .copied()
+            .map(to_hex)
+            .unwrap_or_default()
+    }
+
+    /// The lightest colour
+    pub fn get_foreground(&self) -> String {
+        self.colours.last().copied().map(to_hex).unwrap_or_default()
+    }
+
+    pub fn get_colours(&self) -> Vec<String> {
+        self.colours.iter().copied().map(to_hex).collect()
+    }
+
+    /// The named colours: 'background', 'foreground' and 'color0' up to the palette size
+    pub fn get_variables(&self) -> Vec<(String, String)> {
+        let named = [
+            ("background".to_string(), self.get_background()),
+            ("foreground".to_string(), self.get_foreground()),
+        ];
+
+        named
+            .into_iter()
+            .chain(
+                self.get_colours()
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, colour)| (format!("color{}", index), colour)),
+            )
+            .collect()
+    }
+
+    /// Replaces every '{variable}' in the template with its colour
+    pub fn fill_template(&self, template: &str) -> String {
+        self.get_variables()
+            .iter()
+            .fold(template.to_owned(), |filled, (name, colour)| {
+                filled.replace(&format!("{{{}}}", name), colour)
+            })
+    }
+
+    pub fn format(&self, format: PaletteFormat) -> String {
+        match format {
+            PaletteFormat::Json => serde_json::json!({
+                "background": self.get_background(),
+                "foreground": self.get_foreground(),
+                "colors": self.get_colours(),
+            })
+            .to_string(),
+            PaletteFormat::Css => {
+                let variables = self
+                    .get_variables()
+                    .iter()
+                    .map(|(name, colour)| format!("  --{}: {};\n", name, colour))
+                    .collect::<String>();
+
+                format!(":root {{\n{}}}", variables)
+            }
+            PaletteFormat::Xresources => self
+                .get_variables()
+                .iter()
+                .map(|(name, colour)| format!("*{}: {}", name, colour))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            PaletteFormat::Kitty => self
+                .get_variables()
+                .iter()
+                .map(|(name, colour)| format!("{} {}", name, colour))
+                .collect::<Vec<_>>()
+                .join("\n"),
+        }
+    }
+}
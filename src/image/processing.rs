@@ -0,0 +1,228 @@
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba, RgbaImage};
+use serde::Deserialize;
+
+use crate::{config::ProcessingConfig, CONFIG, IMAGECACHE};
+
+use super::{ImageError, SavedImage};
+
+/// How an image is brought to the configured resolution before assigning it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScaleMode {
+    /// Leave the image as is
+    #[default]
+    None,
+    /// Scale to cover the resolution, cropping the center
+    Fill,
+    /// Scale to fit inside the resolution, the rest is filled with the background colour
+    Fit,
+    /// Scale to the resolution, ignoring the aspect ratio
+    Stretch,
+    /// Like fill, but crops the most detailed part of the image
+    SmartCrop,
+}
+
+impl ScaleMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Fill => "fill",
+            Self::Fit => "fit",
+            Self::Stretch => "stretch",
+            Self::SmartCrop => "smart-crop",
+        }
+    }
+}
+
+/// The steps tried along the image when looking for the most detailed crop
+const SMART_CROP_STEPS: u32 = 16;
+/// The longest side images are shrunk to before measuring detail
+const SMART_CROP_SAMPLE_SIZE: u32 = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessingParameters {
+    pub mode: ScaleMode,
+    pub width: u32,
+    pub height: u32,
+    /// The colour around fitted images
+    pub background: [u8; 3],
+}
+
+fn parse_resolution(resolution: &str) -> Result<(u32, u32), ImageError> {
+    let invalid = || ImageError::InvalidProcessing(resolution.to_owned());
+
+    let (width, height) = resolution.split_once('x').ok_or_else(invalid)?;
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses a '#rrggbb' colour
+pub(super) fn parse_colour(colour: &str) -> Result<[u8; 3], ImageError> {
+    let invalid = || ImageError::InvalidProcessing(colour.to_owned());
+
+    let hex = colour.strip_prefix('#').unwrap_or(colour);
+    if hex.len() != 6 {
+        return Err(invalid());
+    }
+
+    let channel = |index: usize| {
+        hex.get(index..index + 2)
+            .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+            .ok_or_else(invalid)
+    };
+
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+impl ProcessingParameters {
+    /// The parameters of the config, none if images are left as is
+    pub fn from_config() -> Result<Option<Self>, ImageError> {
+        Self::from_processing_config(&CONFIG.processing)
+    }
+
+    pub fn from_processing_config(config: &ProcessingConfig) -> Result<Option<Self>, ImageError> {
+        let resolution = match (config.mode, &config.resolution) {
+            (ScaleMode::None, _) | (_, None) => return Ok(None),
+            (_, Some(resolution)) => resolution,
+        };
+
+        let (width, height) = parse_resolution(resolution)?;
+        let background = match &config.background {
+            Some(background) => parse_colour(background)?,
+            None => [0, 0, 0],
+        };
+
+        Ok(Some(Self {
+            mode: config.mode,
+            width,
+            height,
+            background,
+        }))
+    }
+
+    /// Describes the parameters, used to tell variants of the same image apart
+    pub fn get_key(&self) -> String {
+        format!(
+            "{}-{}x{}-{:02x}{:02x}{:02x}",
+            self.mode.as_str(),
+            self.width,
+            self.height,
+            self.background[0],
+            self.background[1],
+            self.background[2]
+        )
+    }
+
+    fn fit(&self, image: &DynamicImage) -> DynamicImage {
+        let scaled = image.resize(self.width, self.height, FilterType::Lanczos3);
+        let [red, green, blue] = self.background;
+        let mut canvas =
+            RgbaImage::from_pixel(self.width, self.height, Rgba([red, green, blue, 255]));
+
+        let x = (self.width - scaled.width()) / 2;
+        let y = (self.height - scaled.height()) / 2;
+        image::imageops::overlay(&mut canvas, &scaled.to_rgba8(), x as i64, y as i64);
+
+        DynamicImage::ImageRgba8(canvas)
+    }
+
+    /// Crops the window with the highest entropy, at the aspect ratio of the resolution
+    fn smart_crop(&self, image: &DynamicImage) -> DynamicImage {
+        let (source_width, source_height) = image.dimensions();
+        let target_ratio = self.width as f64 / self.height as f64;
+
+        // The largest crop with the target aspect ratio, moved along one axis
+        let (crop_width, crop_height) = if source_width as f64 / source_height as f64 > target_ratio
+        {
+            ((source_height as f64 * target_ratio) as u32, source_height)
+        } else {
+            (source_width, (source_width as f64 / target_ratio) as u32)
+        };
+        let (free_x, free_y) = (source_width - crop_width, source_height - crop_height);
+
+        let sample = image
+            .resize(
+                SMART_CROP_SAMPLE_SIZE,
+                SMART_CROP_SAMPLE_SIZE,
+                FilterType::Triangle,
+            )
+            .into_luma8();
+        let scale = sample.width() as f64 / source_width as f64;
+
+        let entropy_at = |offset_x: u32, offset_y: u32| {
+            let x = ((offset_x as f64 * scale) as u32).min(sample.width() - 1);
+            let y = ((offset_y as f64 * scale) as u32).min(sample.height() - 1);
+            let width = ((crop_width as f64 * scale) as u32).clamp(1, sample.width() - x);
+            let height = ((crop_height as f64 * scale) as u32).clamp(1, sample.height() - y);
+
+            let mut histogram = [0u32; 256];
+            for (_, _, pixel) in sample.view(x, y, width, height).pixels() {
+                histogram[pixel[0] as usize] += 1;
+            }
+
+            let total = (width * height) as f64;
+            histogram
+                .iter()
+                .filter(|count| **count > 0)
+                .map(|count| {
+                    let probability = *count as f64 / total;
+                    -probability * probability.log2()
+                })
+                .sum::<f64>()
+        };
+
+        let (crop_x, crop_y) = (0..=SMART_CROP_STEPS)
+            .map(|step| {
+                (
+                    free_x * step / SMART_CROP_STEPS,
+                    free_y * step / SMART_CROP_STEPS,
+                )
+            })
+            .map(|(x, y)| (x, y, entropy_at(x, y)))
+            .max_by(|(_, _, first), (_, _, second)| first.total_cmp(second))
+            .map(|(x, y, _)| (x, y))
+            .unwrap_or_default();
+
+        image
+            .crop_imm(crop_x, crop_y, crop_width, crop_height)
+            .resize_exact(self.width, self.height, FilterType::Lanczos3)
+    }
+
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        match self.mode {
+            ScaleMode::None => image.clone(),
+            ScaleMode::Fill => image.resize_to_fill(self.width, self.height, FilterType::Lanczos3),
+            ScaleMode::Fit => self.fit(image),
+            ScaleMode::Stretch => image.resize_exact(self.width, self.height, FilterType::Lanczos3),
+            ScaleMode::SmartCrop => self.smart_crop(image),
+        }
+    }
+}
+
+/// Processes the image with the parameters, reusing the cached variant if it exists
+pub fn process(
+    image: &SavedImage,
+    parameters: &ProcessingParameters,
+) -> Result<SavedImage, ImageError> {
+    IMAGECACHE.get_or_create_variant(image, &parameters.get_key(), |decoded| {
+        parameters.apply(&decoded)
+    })
+}
+
+/// The image processed as configured, if it was made already
+pub fn find_processed_for_config(image: SavedImage) -> Result<Option<SavedImage>, ImageError> {
+    match ProcessingParameters::from_config()? {
+        Some(parameters) => IMAGECACHE.find_variant(&image, &parameters.get_key()),
+        None => Ok(Some(image)),
+    }
+}
+
+/// Processes the image as configured, the image itself if processing is disabled
+pub fn process_for_config(image: SavedImage) -> Result<SavedImage, ImageError> {
+    match ProcessingParameters::from_config()? {
+        Some(parameters) => process(&image, &parameters),
+        None => Ok(image),
+    }
+}
@@ -0,0 +1,50 @@
+use std::{fmt::Display, str::FromStr};
+
+use image::{imageops::FilterType, DynamicImage};
+
+/// The hamming distance up to which images are seen as the same picture
+pub const DEFAULT_SIMILARITY_THRESHOLD: u32 = 10;
+
+/// A difference hash of an image, which stays close for resized or re-encoded versions of the same picture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PerceptualHash(u64);
+
+impl PerceptualHash {
+    pub fn from_image(image: &DynamicImage) -> Self {
+        // One column extra, so every row has 8 neighbouring pixel pairs
+        let small = image.resize_exact(9, 8, FilterType::Triangle).into_luma8();
+
+        let mut hash = 0u64;
+        for y in 0..8 {
+            for x in 0..8 {
+                let brighter = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+                hash = (hash << 1) | brighter as u64;
+            }
+        }
+
+        Self(hash)
+    }
+
+    /// The amount of differing bits, 0 means the images look identical
+    pub fn distance(&self, other: &Self) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+
+    pub fn is_similar(&self, other: &Self, threshold: u32) -> bool {
+        self.distance(other) <= threshold
+    }
+}
+
+impl Display for PerceptualHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl FromStr for PerceptualHash {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s, 16).map(Self)
+    }
+}
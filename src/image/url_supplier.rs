@@ -1,16 +1,28 @@
-use std::{collections::HashMap, str::FromStr};
+use std::str::FromStr;
 
 use anyhow::{anyhow, bail};
+use futures_util::future::select_ok;
 use image::ImageFormat;
-use rand::Rng;
-use reqwest::{Response, Url};
-use serde::Deserialize;
+use rand::{seq::SliceRandom, Rng};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
-use crate::IMAGECACHE;
+use crate::{
+    config::GlobalConfig,
+    finder::{check_string_equality, find_best_by_value},
+    state::State,
+    CONFIG, HTTPCLIENT, IMAGECACHE,
+};
 
 use super::{ImageUrl, SearchParameters};
 
+mod json_path;
+mod xml;
+
+use json_path::{get_type_name, JsonPath};
+use xml::XmlResponseDecoder;
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type")]
 enum ImageId {
@@ -42,150 +54,233 @@ enum ResponseResultLocation {
 enum ResponseFormat {
     #[serde(alias = "json", alias = "JSON")]
     Json,
+    /// Rss, atom and other xml feeds, the keys of the response are XPath-like selectors
+    #[serde(alias = "xml", alias = "XML")]
+    Xml,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ResponseTags {
+    key: String,
+    /// The key of the tag name, if the tags are objects
+    name_key: Option<String>,
+}
+
+/// The entries that could be decoded, a malformed entry only fails the page if no entry can be decoded.
+/// Skipped entries are printed to stderr, so they don't mix with the output of the command.
+fn collect_entries<I>(results: I) -> anyhow::Result<Vec<ImageUrl>>
+where
+    I: Iterator<Item = anyhow::Result<ImageUrl>>,
+{
+    let mut entries = vec![];
+    let mut first_error = None;
+
+    for (index, result) in results.enumerate() {
+        match result {
+            Ok(entry) => entries.push(entry),
+            Err(err) => {
+                eprintln!("Skipping entry {}: {}", index, err);
+                first_error.get_or_insert(err);
+            }
+        }
+    }
+
+    match first_error {
+        Some(err) if entries.is_empty() => bail!("Failed to decode any entry: {}", err),
+        _ => Ok(entries),
+    }
 }
 
 struct JsonResponseDecoder {
-    id: ImageId,
-    location: ResponseResultLocation,
-    image_url_key: String,
-    image_type: ImageTypeDecodeMethod,
+    /// None for random ids
+    id: Option<JsonPath>,
+    location: JsonPath,
+    /// Whether the location holds a single entry, instead of a list of entries
+    single_entry: bool,
+    image_url: JsonPath,
+    /// None when the type is read from the path of the url
+    image_type: Option<JsonPath>,
+    /// The tags, and the name inside them if the tags are objects
+    tags: Option<(JsonPath, Option<JsonPath>)>,
+}
+
+fn parse_path(path: &str, setting: &str) -> anyhow::Result<JsonPath> {
+    JsonPath::from_str(path).map_err(|err| anyhow!("Invalid selector for {}: {}", setting, err))
 }
 
 impl JsonResponseDecoder {
-    fn decode_entry(&self, entry: serde_json::Value) -> anyhow::Result<ImageUrl> {
-        if let serde_json::Value::Object(object) = entry {
-            let image_stem = match &self.id {
-                ImageId::Random => rand::thread_rng().gen::<u32>().to_string(),
-                ImageId::Key { key } => {
-                    let image_stem = object
-                        .get(key)
-                        .ok_or(anyhow!("No value for key: {}", key))?;
-
-                    match image_stem {
-                        Value::String(value) => value.clone(),
-                        Value::Number(value) => value.to_string(),
-                        _ => bail!(
-                            "Key for id: {} not of type: String or Number, but of type: {:?}",
-                            key,
-                            image_stem
-                        ),
-                    }
-                }
-            };
+    fn decode_tags(&self, entry: &Value) -> anyhow::Result<Vec<String>> {
+        let Some((tags, name)) = &self.tags else {
+            return Ok(vec![]);
+        };
 
-            let image_url = {
-                let image_url = object
-                    .get(&self.image_url_key)
-                    .ok_or(anyhow!("No value for key: {}", self.image_url_key))?;
+        let decode_tag = |value: &Value| -> anyhow::Result<String> {
+            match (value, name) {
+                (Value::String(value), _) => Ok(value.clone()),
+                (Value::Object(_), Some(name)) => match name.find(value) {
+                    Some(Value::String(value)) => Ok(value.clone()),
+                    _ => bail!("No string value for tag name key: {}", name.get_source()),
+                },
+                _ => bail!(
+                    "Tag in key: {} not of type: String or Object with a name key, but of type: {}",
+                    tags.get_source(),
+                    get_type_name(value)
+                ),
+            }
+        };
 
-                if let serde_json::Value::String(value) = image_url {
-                    value.clone()
-                } else {
-                    bail!(
-                        "Key for image url: {} not of type: String, but of type: {:?}",
-                        self.image_url_key,
-                        image_url
-                    );
-                }
-            };
+        match tags.find(entry) {
+            Some(Value::Array(values)) => values.iter().map(decode_tag).collect(),
+            Some(Value::String(value)) => Ok(value
+                .split(',')
+                .map(|tag| tag.trim().to_owned())
+                .filter(|tag| !tag.is_empty())
+                .collect()),
+            Some(Value::Null) | None => Ok(vec![]),
+            Some(value) => bail!(
+                "Key for tags: {} not of type: Array or String, but of type: {}",
+                tags.get_source(),
+                get_type_name(value)
+            ),
+        }
+    }
 
-            let image_format = match &self.image_type {
-                ImageTypeDecodeMethod::Path => ImageFormat::from_path(&image_url)?,
-                ImageTypeDecodeMethod::Key { key } => {
-                    let image_type = object
-                        .get(key)
-                        .ok_or(anyhow!("No value for key: {}", key))?;
-
-                    if let serde_json::Value::String(value) = image_type {
-                        ImageFormat::from_mime_type(value).ok_or(anyhow!(
-                            "No valid file format for mime type: {}",
-                            image_type
-                        ))?
-                    } else {
-                        bail!(
-                            "Key for image type: {} not of type: String, but of type: {:?}",
-                            key,
-                            image_type
-                        );
-                    }
-                }
-            };
+    fn decode_entry(&self, entry: &Value) -> anyhow::Result<ImageUrl> {
+        let image_stem = match &self.id {
+            None => rand::thread_rng().gen::<u32>().to_string(),
+            Some(id) => match id.get(entry)? {
+                Value::String(value) => value.clone(),
+                Value::Number(value) => value.to_string(),
+                value => bail!(
+                    "Key for id: {} not of type: String or Number, but of type: {}",
+                    id.get_source(),
+                    get_type_name(value)
+                ),
+            },
+        };
 
-            Ok(ImageUrl {
-                stem: image_stem,
-                url: Url::from_str(&image_url)?,
-                image_format,
-            })
-        } else {
-            todo!("Implement a thing here")
-        }
+        let image_url = match self.image_url.get(entry)? {
+            Value::String(value) => value.clone(),
+            value => bail!(
+                "Key for image url: {} not of type: String, but of type: {}",
+                self.image_url.get_source(),
+                get_type_name(value)
+            ),
+        };
+
+        let image_format = match &self.image_type {
+            None => ImageFormat::from_path(&image_url)?,
+            Some(image_type) => match image_type.get(entry)? {
+                Value::String(value) => ImageFormat::from_mime_type(value)
+                    .ok_or(anyhow!("No valid file format for mime type: {}", value))?,
+                value => bail!(
+                    "Key for image type: {} not of type: String, but of type: {}",
+                    image_type.get_source(),
+                    get_type_name(value)
+                ),
+            },
+        };
+
+        Ok(ImageUrl {
+            remote_id: self.id.as_ref().map(|_| image_stem.clone()),
+            stem: image_stem,
+            url: Url::from_str(&image_url)?,
+            image_format,
+            tags: self.decode_tags(entry)?,
+            supplier: None,
+        })
     }
 
-    fn decode_base(
-        &self,
-        data: HashMap<String, serde_json::Value>,
-        parameters: &SearchParameters,
-    ) -> anyhow::Result<ImageUrl> {
-        match &self.location {
-            ResponseResultLocation::Array { key } => {
-                let value = data
-                    .get(key)
-                    .ok_or(anyhow!("Key not found in response: {}", key))?;
-
-                if let serde_json::Value::Array(entries) = value {
-                    if parameters.skip_cache {
-                        for entry in entries {
-                            let decoded_entry = self.decode_entry(entry.to_owned())?;
-                            let cached_image = IMAGECACHE.find(&decoded_entry.stem);
-
-                            if cached_image.is_ok() {
-                                continue;
-                            }
-
-                            return Ok(decoded_entry);
-                        }
-                    }
+    fn decode_cursor(
+        data: &Value,
+        pagination: Option<&PaginationData>,
+    ) -> anyhow::Result<Option<String>> {
+        let Some(PaginationData::Cursor { key, .. }) = pagination else {
+            return Ok(None);
+        };
 
-                    let entry = entries
-                        .first()
-                        .ok_or(anyhow::anyhow!("No entries in response array"))?;
+        match parse_path(key, "the cursor")?.find(data) {
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(Value::Number(value)) => Ok(Some(value.to_string())),
+            Some(Value::Null) | None => Ok(None),
+            Some(value) => bail!(
+                "Key for cursor: {} not of type: String or Number, but of type: {}",
+                key,
+                get_type_name(value)
+            ),
+        }
+    }
 
-                    let decoded_entry = self.decode_entry(entry.to_owned())?;
+    /// The entries at the location, a path ending in '[]' selects the entries themselves
+    fn find_entries<'a>(&self, data: &'a Value) -> anyhow::Result<Vec<&'a Value>> {
+        if self.single_entry {
+            return Ok(vec![self.location.get(data)?]);
+        }
 
-                    Ok(decoded_entry)
-                } else {
-                    bail!("Key: {} not of type array", key)
-                }
-            }
-            ResponseResultLocation::Entry { key } => {
-                let entry = data.get(key).ok_or(anyhow!("No value for key: {}", key))?;
-                let decoded_entry = self.decode_entry(entry.to_owned())?;
+        if self.location.ends_with_each() {
+            return self.location.select(data);
+        }
 
-                Ok(decoded_entry)
-            }
+        match self.location.get(data)? {
+            Value::Array(entries) => Ok(entries.iter().collect()),
+            value => bail!(
+                "Key: {} not of type: Array, but of type: {}",
+                self.location.get_source(),
+                get_type_name(value)
+            ),
         }
     }
 
     pub fn decode(
         &self,
-        response: reqwest::blocking::Response,
-        parameters: &SearchParameters,
-    ) -> anyhow::Result<ImageUrl> {
-        let data: HashMap<String, serde_json::Value> =
-            serde_json::from_slice(response.bytes()?.as_ref())?;
-
-        self.decode_base(data, parameters)
+        response: &[u8],
+        pagination: Option<&PaginationData>,
+    ) -> anyhow::Result<ResponsePage> {
+        let data: Value = serde_json::from_slice(response)?;
+
+        let cursor = Self::decode_cursor(&data, pagination)?;
+        let entries = collect_entries(
+            self.find_entries(&data)?
+                .into_iter()
+                .map(|entry| self.decode_entry(entry)),
+        )?;
+
+        Ok(ResponsePage { entries, cursor })
     }
 }
 
-impl From<ResponseData> for JsonResponseDecoder {
-    fn from(value: ResponseData) -> Self {
-        Self {
-            id: value.id,
-            location: value.location,
-            image_url_key: value.image_url_key,
-            image_type: value.image_type,
-        }
+impl TryFrom<&ResponseData> for JsonResponseDecoder {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &ResponseData) -> Result<Self, Self::Error> {
+        let (location, single_entry) = match &value.location {
+            ResponseResultLocation::Array { key } => (key, false),
+            ResponseResultLocation::Entry { key } => (key, true),
+        };
+
+        Ok(Self {
+            id: match &value.id {
+                ImageId::Key { key } => Some(parse_path(key, "id")?),
+                ImageId::Random => None,
+            },
+            location: parse_path(location, "location")?,
+            single_entry,
+            image_url: parse_path(&value.image_url_key, "image_url_key")?,
+            image_type: match &value.image_type {
+                ImageTypeDecodeMethod::Key { key } => Some(parse_path(key, "image_type")?),
+                ImageTypeDecodeMethod::Path => None,
+            },
+            tags: match &value.tags {
+                Some(tags) => Some((
+                    parse_path(&tags.key, "tags")?,
+                    match &tags.name_key {
+                        Some(name_key) => Some(parse_path(name_key, "tag names")?),
+                        None => None,
+                    },
+                )),
+                None => None,
+            },
+        })
     }
 }
 
@@ -196,43 +291,80 @@ struct ResponseData {
     location: ResponseResultLocation,
     image_url_key: String,
     image_type: ImageTypeDecodeMethod,
+    /// The tags of an entry, used to filter excluded tags if the query can't exclude them
+    tags: Option<ResponseTags>,
 }
 
 impl ResponseData {
     fn process_response(
-        self,
-        response: reqwest::blocking::Response,
-        parameters: &SearchParameters,
-    ) -> anyhow::Result<ImageUrl> {
+        &self,
+        response: &[u8],
+        base_url: &Url,
+        pagination: Option<&PaginationData>,
+    ) -> anyhow::Result<ResponsePage> {
         match self.format {
-            ResponseFormat::Json => JsonResponseDecoder::from(self).decode(response, parameters),
+            ResponseFormat::Json => {
+                JsonResponseDecoder::try_from(self)?.decode(response, pagination)
+            }
+            ResponseFormat::Xml => {
+                XmlResponseDecoder::try_from(self)?.decode(response, base_url, pagination)
+            }
         }
     }
 }
 
+/// The decoded entries of a single response
+struct ResponsePage {
+    entries: Vec<ImageUrl>,
+    /// The cursor pointing to the next page, if the supplier uses cursor pagination
+    cursor: Option<String>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 struct QueryData {
     query: String,
     prefix: Option<String>,
+    /// The prefix for excluded entries, excluded entries are left out of the query if not set
+    negative_prefix: Option<String>,
     seperator: Option<String>,
 }
 
 impl QueryData {
-    pub fn to_query_entry(self, data: Vec<String>) -> (String, String) {
+    pub fn to_query_entry(&self, data: Vec<String>) -> (String, String) {
+        self.to_query_entry_with_exclusions(data, vec![])
+    }
+
+    pub fn to_query_entry_with_exclusions(
+        &self,
+        data: Vec<String>,
+        exclusions: Vec<String>,
+    ) -> (String, String) {
+        let prefix_entry = |prefix: &Option<String>, entry: String| {
+            if let Some(mut prefix) = prefix.clone() {
+                prefix.push_str(&entry);
+                prefix
+            } else {
+                entry
+            }
+        };
+
+        let exclusions = match &self.negative_prefix {
+            Some(_) => exclusions,
+            None => vec![],
+        };
+
         let query_data = data
             .into_iter()
-            .map(|entry| {
-                if let Some(mut prefix) = self.prefix.clone() {
-                    prefix.push_str(&entry);
-                    prefix
-                } else {
-                    entry
-                }
-            })
+            .map(|entry| prefix_entry(&self.prefix, entry))
+            .chain(
+                exclusions
+                    .into_iter()
+                    .map(|entry| prefix_entry(&self.negative_prefix, entry)),
+            )
             .collect::<Vec<_>>()
-            .join(&self.seperator.unwrap_or_default());
+            .join(self.seperator.as_deref().unwrap_or_default());
 
-        (self.query, query_data)
+        (self.query.clone(), query_data)
     }
 }
 
@@ -242,34 +374,353 @@ struct SortData {
     value: String,
 }
 
+fn default_start_page() -> u32 {
+    1
+}
+
+/// The pages searched when the supplier doesn't set 'max_pages'
+const DEFAULT_MAX_PAGES: u32 = 50;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type")]
+enum PaginationData {
+    /// A page number in the query, counting up from start
+    #[serde(alias = "page", alias = "PAGE")]
+    Page {
+        query: String,
+        #[serde(default = "default_start_page")]
+        start: u32,
+        max_pages: Option<u32>,
+    },
+    /// A cursor read from the response, which is passed in the query for the next page
+    #[serde(alias = "cursor", alias = "CURSOR")]
+    Cursor {
+        query: String,
+        key: String,
+        max_pages: Option<u32>,
+    },
+}
+
+impl PaginationData {
+    /// The pages walked through, capped so a supplier that never runs out of pages can't be searched forever
+    fn get_max_pages(&self) -> u32 {
+        match self {
+            Self::Page { max_pages, .. } | Self::Cursor { max_pages, .. } => {
+                max_pages.unwrap_or(DEFAULT_MAX_PAGES)
+            }
+        }
+    }
+
+    fn to_query_entry(&self, position: &SearchPosition) -> Option<(String, String)> {
+        match self {
+            Self::Page { query, start, .. } => {
+                Some((query.clone(), (start + position.page).to_string()))
+            }
+            Self::Cursor { query, .. } => position
+                .cursor
+                .as_ref()
+                .map(|cursor| (query.clone(), cursor.clone())),
+        }
+    }
+}
+
+/// The position in the results of a search, stored in the state to continue where the last search stopped
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct SearchPosition {
+    /// The amount of pages advanced from the first page
+    page: u32,
+    /// The cursor of the current page, if the supplier uses cursor pagination
+    cursor: Option<String>,
+    /// The index of the next entry on the current page
+    index: usize,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct UrlSupplier {
+    /// The name given to the supplier in the config
+    #[serde(skip)]
+    name: String,
     base_url: String,
     response: ResponseData,
     tags: QueryData,
     aspect_ratio: QueryData,
     sort: SortData,
+    pagination: Option<PaginationData>,
 }
 
 impl UrlSupplier {
-    pub fn search(self, parameters: SearchParameters) -> anyhow::Result<ImageUrl> {
-        let result = {
-            let parameters = parameters.clone();
-            reqwest::blocking::Client::new()
-                .get(self.base_url)
-                .query(&[
-                    self.tags.to_query_entry(parameters.tags),
-                    self.aspect_ratio.to_query_entry(parameters.aspect_ratios),
-                    (self.sort.query, self.sort.value),
-                ])
-                .send()?
+    /// Load the supplier with the name from the config, picks a random supplier if no name is given
+    pub fn find_in_config(name: Option<&str>) -> anyhow::Result<Self> {
+        if CONFIG.suppliers.is_empty() {
+            bail!("No suppliers defined in config file.");
+        }
+
+        let supplier_file = match name {
+            Some(supplier_name) => {
+                let (equal, best_value) = find_best_by_value(
+                    supplier_name,
+                    CONFIG.suppliers.iter(),
+                    |value| value.name.as_str(),
+                    |v1, v2| check_string_equality(v1, v2),
+                );
+
+                if let Some(value) = best_value {
+                    if equal {
+                        value
+                    } else {
+                        bail!(
+                            "No supplier for name: {}, did you mean: {}?",
+                            supplier_name,
+                            value.name
+                        );
+                    }
+                } else {
+                    bail!("No suppliers for name: {}", supplier_name);
+                }
+            }
+            None => {
+                // Unwrap here, seeing that there being no entry in the array is checked earlier.
+                CONFIG.suppliers.choose(&mut rand::thread_rng()).unwrap()
+            }
         };
 
-        let response_data = self.response.process_response(result, &parameters)?;
+        let file_path = GlobalConfig::get_config_path().join(&supplier_file.file);
+        let file = std::fs::read_to_string(&file_path);
+
+        match file {
+            Ok(file_content) => Ok(Self {
+                name: supplier_file.name.clone(),
+                ..toml::from_str(&file_content)?
+            }),
+            Err(err) => {
+                bail!(
+                    "Failed to read supplier file: {:?}, reason: {} ",
+                    file_path,
+                    err
+                );
+            }
+        }
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
 
-        Ok(response_data)
+    fn get_query(&self, parameters: &SearchParameters) -> Vec<(String, String)> {
+        vec![
+            self.tags.to_query_entry_with_exclusions(
+                parameters.tags.clone(),
+                parameters.exclude_tags.clone(),
+            ),
+            self.aspect_ratio
+                .to_query_entry(parameters.aspect_ratios.clone()),
+            (self.sort.query.clone(), self.sort.value.clone()),
+        ]
+    }
+
+    /// A key unique to the supplier and query, used to store the search position
+    pub fn get_search_key(&self, parameters: &SearchParameters) -> anyhow::Result<String> {
+        let url = Url::parse_with_params(&self.base_url, self.get_query(parameters))?;
+
+        Ok(url.into())
+    }
+
+    async fn fetch_page(
+        &self,
+        parameters: &SearchParameters,
+        position: &SearchPosition,
+    ) -> anyhow::Result<ResponsePage> {
+        let mut query = self.get_query(parameters);
+        if let Some(page_query) = self
+            .pagination
+            .as_ref()
+            .and_then(|pagination| pagination.to_query_entry(position))
+        {
+            query.push(page_query);
+        }
+
+        let url = Url::parse_with_params(&self.base_url, query)?;
+        let response = HTTPCLIENT.get(url).await?.bytes().await?;
+
+        let mut page = self.response.process_response(
+            &response,
+            &Url::parse(&self.base_url)?,
+            self.pagination.as_ref(),
+        )?;
+        for entry in &mut page.entries {
+            entry.supplier = Some(self.name.clone());
+        }
+
+        Ok(page)
     }
-}
 
-// TODO: This implementation is so fucking ugly and slow, should redo
-// Also use state to keep track of position in array, and step through it, add pagination support for API's aswell
+    /// The position of the first entry on the page after the current one, none if there is no next page
+    fn get_next_position(
+        &self,
+        position: &SearchPosition,
+        page: &ResponsePage,
+    ) -> Option<SearchPosition> {
+        let pagination = self.pagination.as_ref()?;
+
+        if page.entries.is_empty() || position.page + 1 >= pagination.get_max_pages() {
+            return None;
+        }
+
+        let cursor = match pagination {
+            PaginationData::Page { .. } => None,
+            PaginationData::Cursor { .. } => Some(page.cursor.clone()?),
+        };
+
+        Some(SearchPosition {
+            page: position.page + 1,
+            cursor,
+            index: 0,
+        })
+    }
+
+    /// Search for the next results after the position, walking through the pages of the supplier.
+    /// Every page is fetched once, taking up to 'count' results from it before moving on to the next page.
+    /// Starts over from the first page when the results are exhausted.
+    pub async fn search(
+        &self,
+        parameters: &SearchParameters,
+        position: SearchPosition,
+        count: usize,
+    ) -> anyhow::Result<(Vec<ImageUrl>, SearchPosition)> {
+        let mut position = position;
+        let mut started_over = false;
+        let mut fallback = None;
+        let mut results: Vec<ImageUrl> = vec![];
+        // Read once, the cached images don't change while searching
+        let cache_index = match parameters.skip_cache {
+            true => Some(IMAGECACHE.open_index()?),
+            false => None,
+        };
+
+        loop {
+            let page = self.fetch_page(parameters, &position).await?;
+            let next_position = self.get_next_position(&position, &page);
+
+            // The fallback is the first entry on the first page, even if it is in cache
+            if fallback.is_none() && position.page == 0 {
+                fallback = page
+                    .entries
+                    .iter()
+                    .find(|entry| !entry.has_any_tag(&parameters.exclude_tags))
+                    .cloned();
+            }
+
+            // Entries found again after starting over are skipped
+            let found: Vec<(usize, ImageUrl)> = page
+                .entries
+                .into_iter()
+                .enumerate()
+                .skip(position.index)
+                .filter(|(_, entry)| {
+                    !entry.has_any_tag(&parameters.exclude_tags)
+                        && cache_index
+                            .as_ref()
+                            .is_none_or(|index| IMAGECACHE.find_source(index, entry).is_none())
+                        && !results.iter().any(|result| result.url == entry.url)
+                })
+                .collect();
+
+            for (index, entry) in found {
+                results.push(entry);
+
+                if results.len() >= count {
+                    position.index = index + 1;
+                    return Ok((results, position));
+                }
+            }
+
+            position = match next_position {
+                Some(next_position) => next_position,
+                None if !started_over && position != SearchPosition::default() => {
+                    started_over = true;
+                    SearchPosition::default()
+                }
+                None if !results.is_empty() => return Ok((results, SearchPosition::default())),
+                None => {
+                    let entry = fallback.ok_or(anyhow!(
+                        "No images found in the pages of the supplier: {}",
+                        self.name
+                    ))?;
+                    return Ok((
+                        vec![entry],
+                        SearchPosition {
+                            index: 1,
+                            ..Default::default()
+                        },
+                    ));
+                }
+            };
+        }
+    }
+
+    /// The suppliers with the names, or a random supplier if no names are given.
+    /// Every supplier is used if no names are given and 'concurrent_suppliers' is set.
+    pub fn find_many_in_config(names: &[String]) -> anyhow::Result<Vec<Self>> {
+        match names {
+            [] if CONFIG.network.concurrent_suppliers => CONFIG
+                .suppliers
+                .iter()
+                .map(|supplier_file| Self::find_in_config(Some(&supplier_file.name)))
+                .collect(),
+            [] => Ok(vec![Self::find_in_config(None)?]),
+            names => names
+                .iter()
+                .map(|name| Self::find_in_config(Some(name)))
+                .collect(),
+        }
+    }
+
+    /// Searches the suppliers at the same time for their next result, taking the first good one.
+    /// Only the search position of the supplier the result came from moves on.
+    pub async fn search_next<'a>(
+        suppliers: &'a [Self],
+        parameters: &SearchParameters,
+        state: &mut State,
+    ) -> anyhow::Result<(ImageUrl, &'a Self)> {
+        let (image_urls, supplier) =
+            Self::search_next_many(suppliers, parameters, state, 1).await?;
+
+        match image_urls.into_iter().next() {
+            Some(image_url) => Ok((image_url, supplier)),
+            None => bail!("No images found at the supplier: {}", supplier.name),
+        }
+    }
+
+    /// Searches the suppliers at the same time for up to 'count' next results, taking those of the first good one.
+    /// Fewer results are returned when the supplier runs out of results.
+    pub async fn search_next_many<'a>(
+        suppliers: &'a [Self],
+        parameters: &SearchParameters,
+        state: &mut State,
+        count: usize,
+    ) -> anyhow::Result<(Vec<ImageUrl>, &'a Self)> {
+        if suppliers.is_empty() {
+            bail!("No suppliers to search");
+        }
+
+        let searches = suppliers.iter().map(|supplier| {
+            let search_key = supplier.get_search_key(parameters);
+            let position = match &search_key {
+                Ok(search_key) => state.get_search_position(search_key),
+                Err(_) => SearchPosition::default(),
+            };
+
+            Box::pin(async move {
+                let search_key = search_key?;
+                let (image_urls, position) = supplier.search(parameters, position, count).await?;
+
+                anyhow::Ok((image_urls, position, search_key, supplier))
+            })
+        });
+
+        let ((image_urls, position, search_key, supplier), _) = select_ok(searches).await?;
+        state.set_search_position(search_key, position);
+
+        Ok((image_urls, supplier))
+    }
+}
@@ -0,0 +1,313 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::bail;
+use serde_json::Value;
+
+/// A part of a json path
+#[derive(Debug, Clone)]
+enum Segment {
+    /// The value of the key in an object, '.key' or "['key']"
+    Key(String),
+    /// The value at the index in an array, '[0]'
+    Index(usize),
+    /// Every value in an array, '[]' or '[*]'
+    Each,
+}
+
+impl Segment {
+    fn get_expected_type(&self) -> &'static str {
+        match self {
+            Self::Key(_) => "Object",
+            Self::Index(_) | Self::Each => "Array",
+        }
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Key(key) => write!(f, "{}", key),
+            Self::Index(index) => write!(f, "[{}]", index),
+            Self::Each => write!(f, "[]"),
+        }
+    }
+}
+
+pub(super) fn get_type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "Null",
+        Value::Bool(_) => "Bool",
+        Value::Number(_) => "Number",
+        Value::String(_) => "String",
+        Value::Array(_) => "Array",
+        Value::Object(_) => "Object",
+    }
+}
+
+/// A dotted or JSONPath like selector, like 'data', 'response.results[].urls.full' or "$.items[0]['file-type']".
+/// Plain keys are the top level keys they always were, '$' or an empty path is the value itself.
+#[derive(Debug, Clone)]
+pub(super) struct JsonPath {
+    /// The path as written, used in errors
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl FromStr for JsonPath {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let trimmed = source.trim();
+        let mut rest = trimmed.strip_prefix('$').unwrap_or(trimmed);
+        let mut segments = vec![];
+        // The first key of a dotted path has no leading dot, unlike the keys of a JSONPath
+        let mut is_first = rest.len() == trimmed.len();
+
+        while !rest.is_empty() {
+            if let Some(bracket) = rest.strip_prefix('[') {
+                let (segment, after) = match bracket.chars().next() {
+                    Some(quote @ ('\'' | '"')) => {
+                        let Some((key, after)) = bracket[1..].split_once(quote) else {
+                            bail!("Unclosed quote in selector: {}", source);
+                        };
+                        let Some(after) = after.strip_prefix(']') else {
+                            bail!("Unclosed bracket in selector: {}", source);
+                        };
+
+                        (Segment::Key(key.to_owned()), after)
+                    }
+                    _ => {
+                        let Some((index, after)) = bracket.split_once(']') else {
+                            bail!("Unclosed bracket in selector: {}", source);
+                        };
+
+                        let segment = match index.trim() {
+                            "" | "*" => Segment::Each,
+                            index => match index.parse() {
+                                Ok(index) => Segment::Index(index),
+                                Err(_) => bail!("Invalid index: {} in selector: {}", index, source),
+                            },
+                        };
+
+                        (segment, after)
+                    }
+                };
+
+                segments.push(segment);
+                rest = after;
+            } else {
+                let key = match rest.strip_prefix('.') {
+                    Some(key) => key,
+                    None if is_first => rest,
+                    None => bail!(
+                        "Expected '.' or '[' before: {} in selector: {}",
+                        rest,
+                        source
+                    ),
+                };
+
+                let end = key.find(['.', '[']).unwrap_or(key.len());
+                if end == 0 {
+                    bail!("Empty key in selector: {}", source);
+                }
+
+                segments.push(Segment::Key(key[..end].to_owned()));
+                rest = &key[end..];
+            }
+
+            is_first = false;
+        }
+
+        Ok(Self {
+            source: source.to_owned(),
+            segments,
+        })
+    }
+}
+
+impl JsonPath {
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the path ends in '[]', so it selects every value of an array instead of the array
+    pub fn ends_with_each(&self) -> bool {
+        matches!(self.segments.last(), Some(Segment::Each))
+    }
+
+    /// Every value the path leads to, naming the segment that failed to match
+    pub fn select<'a>(&self, value: &'a Value) -> anyhow::Result<Vec<&'a Value>> {
+        let mut values = vec![value];
+
+        for segment in &self.segments {
+            let mut next = vec![];
+
+            for value in values {
+                match (segment, value) {
+                    (Segment::Key(key), Value::Object(object)) => match object.get(key) {
+                        Some(value) => next.push(value),
+                        None => bail!("No value for: {} in selector: {}", segment, self.source),
+                    },
+                    (Segment::Index(index), Value::Array(values)) => match values.get(*index) {
+                        Some(value) => next.push(value),
+                        None => bail!(
+                            "No value for: {} in selector: {}, the array has {} values",
+                            segment,
+                            self.source,
+                            values.len()
+                        ),
+                    },
+                    (Segment::Each, Value::Array(values)) => next.extend(values),
+                    (segment, value) => bail!(
+                        "Expected type: {} for: {} in selector: {}, but found type: {}",
+                        segment.get_expected_type(),
+                        segment,
+                        self.source,
+                        get_type_name(value)
+                    ),
+                }
+            }
+
+            values = next;
+        }
+
+        Ok(values)
+    }
+
+    /// The first value the path leads to
+    pub fn get<'a>(&self, value: &'a Value) -> anyhow::Result<&'a Value> {
+        match self.select(value)?.first() {
+            Some(value) => Ok(value),
+            None => bail!(
+                "No values for selector: {}, the array is empty",
+                self.source
+            ),
+        }
+    }
+
+    /// The first value the path leads to, none if anything along the path is missing
+    pub fn find<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.select(value).ok()?.first().copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn get_response() -> Value {
+        json!({
+            "data": [
+                { "path": "https://example.com/a.png", "file-type": "image/png", "tags": ["a", "b"] },
+                { "path": "https://example.com/b.jpg", "file-type": "image/jpeg", "tags": [] },
+            ],
+            "meta": { "next": "cursor", "count": 2 },
+        })
+    }
+
+    fn select(path: &str) -> anyhow::Result<Vec<Value>> {
+        let response = get_response();
+        let values = JsonPath::from_str(path)?.select(&response)?;
+
+        Ok(values.into_iter().cloned().collect())
+    }
+
+    #[test]
+    fn selects_plain_and_dotted_keys() {
+        assert_eq!(
+            select("meta").unwrap(),
+            [json!({ "next": "cursor", "count": 2 })]
+        );
+        assert_eq!(select("meta.next").unwrap(), [json!("cursor")]);
+        assert_eq!(select("$.meta.count").unwrap(), [json!(2)]);
+    }
+
+    #[test]
+    fn empty_path_selects_the_value_itself() {
+        assert_eq!(select("").unwrap(), [get_response()]);
+        assert_eq!(select("$").unwrap(), [get_response()]);
+    }
+
+    #[test]
+    fn selects_array_indices() {
+        assert_eq!(
+            select("data[1].path").unwrap(),
+            [json!("https://example.com/b.jpg")]
+        );
+        assert_eq!(select("$.data[0].tags[1]").unwrap(), [json!("b")]);
+    }
+
+    #[test]
+    fn selects_quoted_keys() {
+        assert_eq!(
+            select("$.data[0]['file-type']").unwrap(),
+            [json!("image/png")]
+        );
+        assert_eq!(
+            select(r#"data[1]["file-type"]"#).unwrap(),
+            [json!("image/jpeg")]
+        );
+        assert_eq!(select("['meta'].next").unwrap(), [json!("cursor")]);
+    }
+
+    #[test]
+    fn wildcards_select_every_value() {
+        let paths = [
+            json!("https://example.com/a.png"),
+            json!("https://example.com/b.jpg"),
+        ];
+        assert_eq!(select("data[].path").unwrap(), paths);
+        assert_eq!(select("$.data[*].path").unwrap(), paths);
+        assert_eq!(select("data[].tags[]").unwrap(), [json!("a"), json!("b")]);
+
+        assert!(JsonPath::from_str("data[]").unwrap().ends_with_each());
+        assert!(!JsonPath::from_str("data[].path").unwrap().ends_with_each());
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        for path in [
+            "data[0",
+            "data['path",
+            "data['path'",
+            "data[x]",
+            "data[-1]",
+            "data..path",
+            "data.",
+            "data[0]path",
+            "$meta",
+        ] {
+            assert!(
+                JsonPath::from_str(path).is_err(),
+                "{} should not parse",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn fails_on_missing_values_and_wrong_types() {
+        assert!(select("missing").is_err());
+        assert!(select("data[2]").is_err());
+        assert!(select("meta[0]").is_err());
+        assert!(select("data.path").is_err());
+        assert!(select("meta.next.value").is_err());
+    }
+
+    #[test]
+    fn get_takes_the_first_value() {
+        let response = get_response();
+        let path = JsonPath::from_str("data[].path").unwrap();
+        assert_eq!(path.get(&response).unwrap(), "https://example.com/a.png");
+
+        let empty = JsonPath::from_str("data[1].tags[]").unwrap();
+        assert!(empty.get(&response).is_err());
+        assert!(empty.find(&response).is_none());
+        assert!(JsonPath::from_str("missing")
+            .unwrap()
+            .find(&response)
+            .is_none());
+    }
+}
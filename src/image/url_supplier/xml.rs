@@ -0,0 +1,603 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+use image::ImageFormat;
+use rand::Rng;
+use reqwest::Url;
+use roxmltree::{Document, Node, ParsingOptions};
+
+use crate::image::ImageUrl;
+
+use super::{
+    collect_entries, ImageId, ImageTypeDecodeMethod, PaginationData, ResponseData, ResponsePage,
+    ResponseResultLocation,
+};
+
+/// An element step of a selector, like 'item' or "link[@rel='enclosure']"
+#[derive(Debug, Clone)]
+struct Step {
+    /// The step as written, used in errors
+    source: String,
+    /// Whether the step matches elements anywhere below, instead of only children
+    descendants: bool,
+    /// The element name, optionally with a namespace prefix, '*' matches every element
+    name: String,
+    /// An attribute the element must have, and optionally its value
+    attribute: Option<(String, Option<String>)>,
+}
+
+impl Step {
+    fn parse(source: &str, descendants: bool) -> anyhow::Result<Self> {
+        let (name, attribute) = match source.split_once('[') {
+            Some((name, filter)) => {
+                let filter = filter
+                    .strip_suffix(']')
+                    .and_then(|filter| filter.strip_prefix('@'))
+                    .ok_or(anyhow!("Invalid filter in step: {}", source))?;
+
+                let attribute = match filter.split_once('=') {
+                    Some((attribute, value)) => {
+                        let value = value.trim();
+                        let value = value
+                            .strip_prefix('\'')
+                            .and_then(|value| value.strip_suffix('\''))
+                            .or_else(|| {
+                                value
+                                    .strip_prefix('"')
+                                    .and_then(|value| value.strip_suffix('"'))
+                            })
+                            .ok_or(anyhow!("Unquoted value in step: {}", source))?;
+
+                        (attribute.trim().to_owned(), Some(value.to_owned()))
+                    }
+                    None => (filter.trim().to_owned(), None),
+                };
+
+                (name, Some(attribute))
+            }
+            None => (source, None),
+        };
+
+        if name.is_empty() {
+            bail!("No element name in step: {}", source);
+        }
+
+        Ok(Self {
+            source: source.to_owned(),
+            descendants,
+            name: name.to_owned(),
+            attribute,
+        })
+    }
+
+    fn matches(&self, node: &Node) -> bool {
+        node.is_element() && self.matches_name(node) && self.matches_attribute(node)
+    }
+
+    /// Names without a prefix match elements of every namespace, like the default namespace of atom feeds
+    fn matches_name(&self, node: &Node) -> bool {
+        let tag_name = node.tag_name();
+
+        match self.name.split_once(':') {
+            _ if self.name == "*" => true,
+            Some((prefix, name)) => {
+                tag_name.name() == name
+                    && tag_name.namespace().is_some()
+                    && tag_name.namespace() == node.lookup_namespace_uri(Some(prefix))
+            }
+            None => tag_name.name() == self.name,
+        }
+    }
+
+    fn matches_attribute(&self, node: &Node) -> bool {
+        match &self.attribute {
+            Some((attribute, Some(value))) => node.attribute(attribute.as_str()) == Some(value),
+            Some((attribute, None)) => node.has_attribute(attribute.as_str()),
+            None => true,
+        }
+    }
+}
+
+/// What a selector reads from the elements it matches
+#[derive(Debug, Clone)]
+enum Target {
+    /// The text inside the element, '/text()' or nothing at the end of the selector
+    Text,
+    /// The value of the attribute, '/@name' at the end of the selector
+    Attribute(String),
+}
+
+/// An XPath-like selector, like 'channel/item', '//entry', "link[@rel='enclosure']/@href" or 'media:content/@url'.
+/// Selectors start at the current entry, or at the document for a leading '/'.
+#[derive(Debug, Clone)]
+struct Selector {
+    /// The selector as written, used in errors
+    source: String,
+    absolute: bool,
+    steps: Vec<Step>,
+    target: Target,
+}
+
+/// Splits the selector on slashes outside of filters, an empty part stands for '//'
+fn split_selector(selector: &str) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut in_filter = false;
+    let mut quote = None;
+
+    for (index, character) in selector.char_indices() {
+        match (character, quote) {
+            ('\'' | '"', None) if in_filter => quote = Some(character),
+            (character, Some(open)) if character == open => quote = None,
+            ('[', None) => in_filter = true,
+            (']', None) => in_filter = false,
+            ('/', None) if !in_filter => {
+                parts.push(&selector[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&selector[start..]);
+
+    parts
+}
+
+impl FromStr for Selector {
+    type Err = anyhow::Error;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let source = source.trim();
+        if source.is_empty() {
+            bail!("Empty selector");
+        }
+
+        // A leading '//' is left as an empty part, which searches the whole document
+        let (absolute, selector) = match source.strip_prefix('/') {
+            Some(selector) => (true, selector),
+            None => (false, source),
+        };
+
+        let mut steps = vec![];
+        let mut target = None;
+        let mut descendants = false;
+
+        for part in split_selector(selector) {
+            if target.is_some() {
+                bail!("Nothing can follow: {} in selector: {}", part, source);
+            }
+
+            match part {
+                "" if descendants => bail!("Empty step in selector: {}", source),
+                "" => descendants = true,
+                "." => {}
+                "text()" => target = Some(Target::Text),
+                part => match part.strip_prefix('@') {
+                    Some(attribute) => target = Some(Target::Attribute(attribute.to_owned())),
+                    None => {
+                        steps.push(Step::parse(part, descendants)?);
+                        descendants = false;
+                    }
+                },
+            }
+        }
+
+        if descendants {
+            bail!("No step after '//' in selector: {}", source);
+        }
+
+        Ok(Self {
+            source: source.to_owned(),
+            absolute,
+            steps,
+            target: target.unwrap_or(Target::Text),
+        })
+    }
+}
+
+impl Selector {
+    /// The elements the steps lead to, or the first step that matched nothing
+    fn select<'a, 'input>(&self, node: Node<'a, 'input>) -> Result<Vec<Node<'a, 'input>>, &Step> {
+        let start = match self.absolute {
+            true => node.document().root(),
+            false => node,
+        };
+        let mut nodes = vec![start];
+
+        for step in &self.steps {
+            nodes = nodes
+                .iter()
+                .flat_map(|node| match step.descendants {
+                    true => node.descendants().skip(1).collect::<Vec<_>>(),
+                    false => node.children().collect(),
+                })
+                .filter(|node| step.matches(node))
+                .collect();
+            // Descendants of nested elements are found more than once
+            nodes.sort_by_key(|node| node.id().get());
+            nodes.dedup_by_key(|node| node.id().get());
+
+            if nodes.is_empty() {
+                return Err(step);
+            }
+        }
+
+        Ok(nodes)
+    }
+
+    /// Every element the selector matches, empty if nothing matches
+    fn find_all<'a, 'input>(&self, node: Node<'a, 'input>) -> Vec<Node<'a, 'input>> {
+        self.select(node).unwrap_or_default()
+    }
+
+    fn read(&self, node: &Node) -> Option<String> {
+        match &self.target {
+            Target::Text => Some(
+                node.descendants()
+                    .filter(|node| node.is_text())
+                    .filter_map(|node| node.text())
+                    .collect::<String>()
+                    .trim()
+                    .to_owned(),
+            ),
+            Target::Attribute(attribute) => node
+                .attribute(attribute.as_str())
+                .map(|value| value.trim().to_owned()),
+        }
+    }
+
+    /// The values of every element the selector matches, empty if nothing matches
+    fn read_all(&self, node: Node) -> Vec<String> {
+        self.find_all(node)
+            .iter()
+            .filter_map(|node| self.read(node))
+            .collect()
+    }
+
+    /// The value of the first element the selector matches, naming the part that failed to match
+    fn read_first(&self, node: Node) -> anyhow::Result<String> {
+        let nodes = match self.select(node) {
+            Ok(nodes) => nodes,
+            Err(step) => bail!(
+                "No element for: {} in selector: {}",
+                step.source,
+                self.source
+            ),
+        };
+
+        match nodes.iter().find_map(|node| self.read(node)) {
+            Some(value) if !value.is_empty() => Ok(value),
+            _ => match &self.target {
+                Target::Text => bail!("No text in selector: {}", self.source),
+                Target::Attribute(attribute) => {
+                    bail!("No attribute: @{} in selector: {}", attribute, self.source)
+                }
+            },
+        }
+    }
+}
+
+fn parse_selector(selector: &str, setting: &str) -> anyhow::Result<Selector> {
+    Selector::from_str(selector).map_err(|err| anyhow!("Invalid selector for {}: {}", setting, err))
+}
+
+/// Decodes xml responses, like rss and atom feeds, the keys of the response data are selectors
+pub(super) struct XmlResponseDecoder {
+    /// None for random ids
+    id: Option<Selector>,
+    location: Selector,
+    /// Whether the location holds a single entry, instead of a list of entries
+    single_entry: bool,
+    image_url: Selector,
+    /// None when the type is read from the path of the url
+    image_type: Option<Selector>,
+    /// The tag elements, and the name inside them if the name is not their text
+    tags: Option<(Selector, Option<Selector>)>,
+}
+
+impl TryFrom<&ResponseData> for XmlResponseDecoder {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &ResponseData) -> Result<Self, Self::Error> {
+        let (location, single_entry) = match &value.location {
+            ResponseResultLocation::Array { key } => (key, false),
+            ResponseResultLocation::Entry { key } => (key, true),
+        };
+
+        Ok(Self {
+            id: match &value.id {
+                ImageId::Key { key } => Some(parse_selector(key, "id")?),
+                ImageId::Random => None,
+            },
+            location: parse_selector(location, "location")?,
+            single_entry,
+            image_url: parse_selector(&value.image_url_key, "image_url_key")?,
+            image_type: match &value.image_type {
+                ImageTypeDecodeMethod::Key { key } => Some(parse_selector(key, "image_type")?),
+                ImageTypeDecodeMethod::Path => None,
+            },
+            tags: match &value.tags {
+                Some(tags) => Some((
+                    parse_selector(&tags.key, "tags")?,
+                    match &tags.name_key {
+                        Some(name_key) => Some(parse_selector(name_key, "tag names")?),
+                        None => None,
+                    },
+                )),
+                None => None,
+            },
+        })
+    }
+}
+
+impl XmlResponseDecoder {
+    /// Tags are the text of the tag elements, or comma separated in a single element
+    fn decode_tags(&self, entry: Node) -> Vec<String> {
+        let Some((tags, name)) = &self.tags else {
+            return vec![];
+        };
+
+        let values = match name {
+            Some(name) => tags
+                .find_all(entry)
+                .into_iter()
+                .flat_map(|tag| name.read_all(tag))
+                .collect(),
+            None => tags.read_all(entry),
+        };
+
+        values
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|tag| tag.trim().to_owned())
+            .filter(|tag| !tag.is_empty())
+            .collect()
+    }
+
+    /// Relative image urls, like '/th?id=...', are resolved against the base url of the supplier
+    fn decode_entry(&self, entry: Node, base_url: &Url) -> anyhow::Result<ImageUrl> {
+        let image_stem = match &self.id {
+            Some(id) => id.read_first(entry)?,
+            None => rand::thread_rng().gen::<u32>().to_string(),
+        };
+
+        let image_url = base_url.join(&self.image_url.read_first(entry)?)?;
+
+        let image_format = match &self.image_type {
+            None => ImageFormat::from_path(image_url.path())?,
+            Some(image_type) => {
+                let image_type = image_type.read_first(entry)?;
+
+                ImageFormat::from_mime_type(&image_type).ok_or(anyhow!(
+                    "No valid file format for mime type: {}",
+                    image_type
+                ))?
+            }
+        };
+
+        Ok(ImageUrl {
+            remote_id: self.id.as_ref().map(|_| image_stem.clone()),
+            stem: image_stem,
+            url: image_url,
+            image_format,
+            tags: self.decode_tags(entry),
+            supplier: None,
+        })
+    }
+
+    pub fn decode(
+        &self,
+        response: &[u8],
+        base_url: &Url,
+        pagination: Option<&PaginationData>,
+    ) -> anyhow::Result<ResponsePage> {
+        let text = std::str::from_utf8(response)?;
+        // Feeds sometimes declare a doctype
+        let options = ParsingOptions {
+            allow_dtd: true,
+            ..Default::default()
+        };
+        let document = Document::parse_with_options(text, options)?;
+        let root = document.root();
+
+        let cursor = match pagination {
+            Some(PaginationData::Cursor { key, .. }) => parse_selector(key, "the cursor")?
+                .read_all(root)
+                .into_iter()
+                .find(|cursor| !cursor.is_empty()),
+            _ => None,
+        };
+
+        let entries = match self.single_entry {
+            true => match self.location.select(root) {
+                Ok(entries) => vec![self.decode_entry(entries[0], base_url)?],
+                Err(step) => bail!(
+                    "No element for: {} in selector: {}",
+                    step.source,
+                    self.location.source
+                ),
+            },
+            // A feed without items is empty, not broken, items without an image are skipped
+            false => collect_entries(
+                self.location
+                    .find_all(root)
+                    .into_iter()
+                    .map(|entry| self.decode_entry(entry, base_url)),
+            )?,
+        };
+
+        Ok(ResponsePage { entries, cursor })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = r#"<?xml version="1.0"?>
+<rss xmlns:media="http://search.yahoo.com/mrss/">
+  <channel>
+    <title>Wallpapers</title>
+    <item>
+      <guid>first</guid>
+      <link rel="alternate" href="https://example.com/first"/>
+      <link rel="enclosure" href="/images/first.png"/>
+      <media:content url="https://cdn.example.com/first.jpg" type="image/jpeg"/>
+      <category>forest, night</category>
+    </item>
+    <item>
+      <guid>second</guid>
+      <link rel="enclosure" href="https://example.com/images/second.jpg"/>
+      <category>sea</category>
+      <category>day</category>
+    </item>
+  </channel>
+</rss>"#;
+
+    fn read_all(selector: &str, document: &Document) -> Vec<String> {
+        Selector::from_str(selector)
+            .unwrap()
+            .read_all(document.root())
+    }
+
+    fn get_items<'a, 'input>(document: &'a Document<'input>) -> Vec<Node<'a, 'input>> {
+        Selector::from_str("//item")
+            .unwrap()
+            .find_all(document.root())
+    }
+
+    #[test]
+    fn selects_child_and_descendant_elements() {
+        let document = Document::parse(FEED).unwrap();
+
+        assert_eq!(read_all("rss/channel/title", &document), ["Wallpapers"]);
+        assert_eq!(read_all("//guid", &document), ["first", "second"]);
+        assert_eq!(
+            read_all("rss//item/guid/text()", &document),
+            ["first", "second"]
+        );
+        assert_eq!(read_all("//channel/*/guid", &document), ["first", "second"]);
+    }
+
+    #[test]
+    fn selects_attributes_and_filters_on_them() {
+        let document = Document::parse(FEED).unwrap();
+
+        assert_eq!(
+            read_all("//link[@rel='enclosure']/@href", &document),
+            ["/images/first.png", "https://example.com/images/second.jpg"]
+        );
+        assert_eq!(
+            read_all(r#"//link[@rel="alternate"]/@href"#, &document),
+            ["https://example.com/first"]
+        );
+        assert_eq!(read_all("//link[@rel]/@href", &document).len(), 3);
+        assert!(read_all("//link/@missing", &document).is_empty());
+    }
+
+    #[test]
+    fn matches_namespace_prefixes() {
+        let document = Document::parse(FEED).unwrap();
+
+        assert_eq!(
+            read_all("//media:content/@url", &document),
+            ["https://cdn.example.com/first.jpg"]
+        );
+        assert_eq!(read_all("//content/@type", &document), ["image/jpeg"]);
+        assert!(read_all("//other:content/@url", &document).is_empty());
+    }
+
+    #[test]
+    fn selectors_start_at_the_entry_unless_absolute() {
+        let document = Document::parse(FEED).unwrap();
+        let item = get_items(&document)[1];
+
+        let relative = Selector::from_str("guid").unwrap();
+        assert_eq!(relative.read_first(item).unwrap(), "second");
+        let current = Selector::from_str("./guid").unwrap();
+        assert_eq!(current.read_first(item).unwrap(), "second");
+        let absolute = Selector::from_str("/rss/channel/title").unwrap();
+        assert_eq!(absolute.read_first(item).unwrap(), "Wallpapers");
+    }
+
+    #[test]
+    fn read_first_names_what_is_missing() {
+        let document = Document::parse(FEED).unwrap();
+        let item = get_items(&document)[0];
+
+        let missing_step = Selector::from_str("enclosure/@url").unwrap();
+        let err = missing_step.read_first(item).unwrap_err().to_string();
+        assert!(err.contains("enclosure"), "{}", err);
+
+        let missing_attribute = Selector::from_str("link/@src").unwrap();
+        let err = missing_attribute.read_first(item).unwrap_err().to_string();
+        assert!(err.contains("@src"), "{}", err);
+    }
+
+    #[test]
+    fn rejects_malformed_selectors() {
+        for selector in [
+            "",
+            "  ",
+            "item//",
+            "item///guid",
+            "@href/item",
+            "text()/item",
+            "link[rel='enclosure']",
+            "link[@rel=enclosure]",
+            "link[@rel='enclosure'",
+            "[@rel]",
+        ] {
+            assert!(
+                Selector::from_str(selector).is_err(),
+                "{:?} should not parse",
+                selector
+            );
+        }
+    }
+
+    fn get_decoder() -> XmlResponseDecoder {
+        XmlResponseDecoder {
+            id: Some(Selector::from_str("guid").unwrap()),
+            location: Selector::from_str("//item").unwrap(),
+            single_entry: false,
+            image_url: Selector::from_str("link[@rel='enclosure']/@href").unwrap(),
+            image_type: None,
+            tags: Some((Selector::from_str("category").unwrap(), None)),
+        }
+    }
+
+    #[test]
+    fn resolves_relative_image_urls_against_the_base_url() {
+        let base_url = Url::parse("https://example.com/feed/rss.xml").unwrap();
+        let page = get_decoder()
+            .decode(FEED.as_bytes(), &base_url, None)
+            .unwrap();
+
+        let urls: Vec<String> = page
+            .entries
+            .iter()
+            .map(|entry| entry.url.to_string())
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/images/first.png",
+                "https://example.com/images/second.jpg"
+            ]
+        );
+        assert_eq!(page.entries[0].image_format, ImageFormat::Png);
+        assert_eq!(page.entries[0].remote_id.as_deref(), Some("first"));
+    }
+
+    #[test]
+    fn splits_tags_on_commas_and_elements() {
+        let base_url = Url::parse("https://example.com/").unwrap();
+        let page = get_decoder()
+            .decode(FEED.as_bytes(), &base_url, None)
+            .unwrap();
+
+        assert_eq!(page.entries[0].tags, ["forest", "night"]);
+        assert_eq!(page.entries[1].tags, ["sea", "day"]);
+    }
+}
@@ -4,20 +4,19 @@ pub mod image;
 pub use cli::Program;
 use config::GlobalConfig;
 use image::cache::ImageCache;
+use network::HttpClient;
 pub mod category;
 pub mod collections;
+pub mod daemon;
 pub mod finder;
+pub mod history;
+pub mod hooks;
+pub mod network;
 pub mod state;
 
 lazy_static::lazy_static! {
     pub static ref BASEDIRECTORIES: directories::ProjectDirs = directories::ProjectDirs::from("", "", env!("CARGO_PKG_NAME")).expect("Failed to get project directories");
     pub static ref CONFIG: GlobalConfig = GlobalConfig::read().expect("Failed to open config.");
-    pub static ref IMAGECACHE: ImageCache = {
-        let cache = ImageCache;
-        match cache.cleanup_cache() {
-            Ok(_) => {},
-            Err(err) => println!("Failed to clean the cache: {:?}", err)
-        }
-        cache
-    };
+    pub static ref HTTPCLIENT: HttpClient = HttpClient::new().expect("Failed to create the http client.");
+    pub static ref IMAGECACHE: ImageCache = ImageCache;
 }
@@ -0,0 +1,131 @@
+use std::{future::Future, io, time::Duration};
+
+use reqwest::{header::RETRY_AFTER, Client, Response, StatusCode, Url};
+use thiserror::Error;
+use tokio::runtime::Runtime;
+
+use crate::CONFIG;
+
+/// The seconds to wait for a connection to the server
+const DEFAULT_CONNECT_TIMEOUT: u64 = 10;
+/// The seconds to wait between reads of the response
+const DEFAULT_READ_TIMEOUT: u64 = 30;
+/// How often a failed request is tried again
+const DEFAULT_RETRIES: u32 = 3;
+/// The wait before the first retry, doubled for every following retry
+const BASE_BACKOFF: Duration = Duration::from_millis(500);
+/// The longest wait asked for by 'Retry-After' that is honoured, and the longest wait between retries
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(60);
+/// The megabytes an image download may take up
+pub const DEFAULT_MAX_DOWNLOAD_SIZE: u64 = 100;
+const DEFAULT_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error("Failed to start the network runtime: {0}")]
+    RuntimeError(io::Error),
+    #[error("The request failed: {0}")]
+    RequestError(reqwest::Error),
+    #[error("The server responded with: {0}, for: {1}")]
+    StatusError(StatusCode, Url),
+}
+
+/// Whether the server might answer the request the next time
+fn is_retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+/// The wait asked for by the 'Retry-After' header, either in seconds or as a date
+fn get_retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?;
+
+    let wait = match value.trim().parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                .to_std()
+                .unwrap_or_default()
+        }
+    };
+
+    Some(wait.min(MAX_RETRY_AFTER))
+}
+
+/// Sends the requests of every supplier and image download
+pub struct HttpClient {
+    client: Client,
+    runtime: Runtime,
+    retries: u32,
+}
+
+impl HttpClient {
+    pub fn new() -> Result<Self, NetworkError> {
+        let config = &CONFIG.network;
+
+        let client = Client::builder()
+            .user_agent(
+                config
+                    .user_agent
+                    .clone()
+                    .unwrap_or_else(|| DEFAULT_USER_AGENT.to_owned()),
+            )
+            .connect_timeout(Duration::from_secs(
+                config.connect_timeout.unwrap_or(DEFAULT_CONNECT_TIMEOUT),
+            ))
+            .read_timeout(Duration::from_secs(
+                config.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT),
+            ))
+            .build();
+        let client = match client {
+            Ok(client) => client,
+            Err(err) => return Err(NetworkError::RequestError(err)),
+        };
+
+        let runtime = match tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(err) => return Err(NetworkError::RuntimeError(err)),
+        };
+
+        Ok(Self {
+            client,
+            runtime,
+            retries: config.retries.unwrap_or(DEFAULT_RETRIES),
+        })
+    }
+
+    /// Runs the future to completion on the network runtime, must not be called from within the runtime
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    /// Sends a get request, retrying with exponential backoff on failed connections, timeouts and 5xx or 429 responses
+    pub async fn get(&self, url: Url) -> Result<Response, NetworkError> {
+        let mut attempt = 0;
+
+        loop {
+            let result = self.client.get(url.clone()).send().await;
+            let retry_after = match result {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if is_retryable(response.status()) && attempt < self.retries => {
+                    get_retry_after(&response)
+                }
+                Ok(response) => return Err(NetworkError::StatusError(response.status(), url)),
+                Err(err) if (err.is_connect() || err.is_timeout()) && attempt < self.retries => {
+                    None
+                }
+                Err(err) => return Err(NetworkError::RequestError(err)),
+            };
+
+            // Many retries would overflow the doubled wait, so it is capped like a wait asked for by the server
+            let backoff = BASE_BACKOFF
+                .checked_mul(2u32.saturating_pow(attempt))
+                .map_or(MAX_RETRY_AFTER, |backoff| backoff.min(MAX_RETRY_AFTER));
+            tokio::time::sleep(retry_after.unwrap_or(backoff)).await;
+            attempt += 1;
+        }
+    }
+}
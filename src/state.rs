@@ -1,10 +1,29 @@
-use std::{io, path::PathBuf, str::Split};
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
 
-use crate::image::ImageError;
+pub mod backend;
+mod command;
+
+use crate::{
+    history::{History, HistoryEntry, HistoryError},
+    hooks::{HookContext, HookError, HookEvent},
+    image::{
+        effects::Effects,
+        processing::{find_processed_for_config, process_for_config},
+        ImageError, SearchPosition,
+    },
+};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
-use crate::{collections::Collection, image::SavedImage, BASEDIRECTORIES, CONFIG};
+use crate::{
+    collections::{manifest::CollectionManifest, Collection},
+    image::SavedImage,
+    BASEDIRECTORIES, IMAGECACHE,
+};
 
 #[derive(Debug, Error)]
 pub enum StateError {
@@ -16,62 +35,34 @@ pub enum StateError {
     AssignCommandError(String),
     #[error("No image has been set")]
     NoImageSet,
+    #[error("A history related failure occured: {0}")]
+    HistoryError(HistoryError),
+    #[error("The wallpaper change was stopped by a pre set hook: {0}")]
+    Vetoed(HookError),
+    #[error("The command for assigning a wallpaper is invalid: {0}")]
+    InvalidCommand(String),
 }
 
 lazy_static::lazy_static! { static ref STATE_FILE: PathBuf = BASEDIRECTORIES.data_dir().join("state.toml"); }
 
-struct SetImageCommand<'a> {
-    program: &'a str,
-    args: Split<'a, char>,
-}
-
-impl<'a> SetImageCommand<'a> {
-    pub fn new(command: &'a str) -> SetImageCommand<'a> {
-        let (program, args) = command.split_once(' ').unwrap_or((command, ""));
-        let args = args.split(' ');
-
-        SetImageCommand { program, args }
-    }
-
-    pub fn apply(&self, image: &SavedImage) -> Result<(), StateError> {
-        let image_path = match image.get_absolute_path_as_string() {
-            Ok(image_path) => image_path,
-            Err(err) => return Err(StateError::ImageError(err)),
-        };
-        let used_args =
-            self.args
-                .clone()
-                .into_iter()
-                .map(|v| if v == "{path}" { &image_path } else { v });
-        let result = std::process::Command::new(self.program)
-            .args(used_args)
-            .output();
-
-        match result {
-            Ok(output) => {
-                if output.status.success() {
-                    Ok(())
-                } else {
-                    Err(StateError::AssignCommandError(
-                        String::from_utf8_lossy(&output.stderr).into_owned(),
-                    ))
-                }
-            }
-            Err(err) => Err(StateError::FsError(err)),
-        }
-    }
-}
-
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum ImageStateType {
-    Image { path: String },
-    Collection { name: String, image_path: String },
+    Image {
+        path: String,
+        /// The effects of the category the image was fetched for, applied when it is assigned
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        effects: Vec<String>,
+    },
+    Collection {
+        name: String,
+        image_path: String,
+    },
 }
 
 impl ImageStateType {
     pub fn get_image_path(&self) -> &str {
         match self {
-            Self::Image { path } => path,
+            Self::Image { path, .. } => path,
             Self::Collection {
                 name: _,
                 image_path,
@@ -91,7 +82,14 @@ impl ImageStateType {
 
 #[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct State {
+    /// The image set for all outputs
     image: Option<ImageStateType>,
+    /// Images set for a specific output, keyed by output name
+    #[serde(default)]
+    outputs: BTreeMap<String, ImageStateType>,
+    /// The position in the results of previous searches, keyed by supplier and query
+    #[serde(default)]
+    search_positions: BTreeMap<String, SearchPosition>,
 }
 
 impl State {
@@ -105,45 +103,280 @@ impl State {
         }
     }
 
-    pub fn assign_current_image(&self) -> Result<(), StateError> {
-        if let Some(current_image) = &self.image {
-            if let Some(set_command) = &CONFIG.set_command {
-                let image = current_image.load_saved_image()?;
-                let command = SetImageCommand::new(set_command);
-                command.apply(&image)?;
-
-                Ok(())
-            } else {
-                Err(StateError::AssignCommandError(
-                    "Not assign command specified".to_string(),
-                ))
+    /// The images currently set and the processed variants of them that are displayed, which the cache has to keep
+    pub fn get_displayed_paths(&self) -> Vec<PathBuf> {
+        self.image
+            .iter()
+            .chain(self.outputs.values())
+            .flat_map(|image_state| {
+                let displayed = Self::find_displayed_image(image_state)
+                    .map(|image| image.get_path().to_path_buf());
+
+                [Some(PathBuf::from(image_state.get_image_path())), displayed]
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// The processed variant of the image that is displayed, if it was made already
+    fn find_displayed_image(image_state: &ImageStateType) -> Option<SavedImage> {
+        let effects =
+            Self::get_effects(image_state, Self::get_manifest(image_state).as_ref()).ok()?;
+        let image = image_state.load_saved_image().ok()?;
+        let processed = find_processed_for_config(image).ok()??;
+
+        effects.find_applied_to(processed).ok()?
+    }
+
+    /// The manifest of the collection the image is from
+    fn get_manifest(image_state: &ImageStateType) -> Option<CollectionManifest> {
+        match image_state {
+            ImageStateType::Collection { name, .. } => Collection::open(name)
+                .ok()
+                .map(|collection| collection.get_directory().get_manifest().clone()),
+            ImageStateType::Image { .. } => None,
+        }
+    }
+
+    /// The effects of the collection the image is from, or those stored with the image
+    fn get_effects(
+        image_state: &ImageStateType,
+        manifest: Option<&CollectionManifest>,
+    ) -> Result<Effects, StateError> {
+        match (manifest, image_state) {
+            (Some(manifest), _) => Effects::from_list(&manifest.effects),
+            (None, ImageStateType::Image { effects, .. }) => Effects::from_list(effects),
+            (None, ImageStateType::Collection { .. }) => Ok(Effects::default()),
+        }
+        .map_err(StateError::ImageError)
+    }
+
+    fn assign_image(
+        &self,
+        image_state: &ImageStateType,
+        output: Option<&str>,
+    ) -> Result<(), StateError> {
+        let manifest = Self::get_manifest(image_state);
+
+        // The manifest of a collection can override the backend of the config with its own command
+        let backend = backend::get_backend(
+            manifest
+                .as_ref()
+                .and_then(|manifest| manifest.set_command.as_ref()),
+        )?;
+
+        let effects = Self::get_effects(image_state, manifest.as_ref())?;
+
+        let original = image_state.load_saved_image()?;
+        IMAGECACHE
+            .touch(&original)
+            .map_err(StateError::ImageError)?;
+        let image = process_for_config(original.clone())
+            .and_then(|image| effects.apply_to(&image))
+            .map_err(StateError::ImageError)?;
+        let collection = match image_state {
+            ImageStateType::Collection { name, .. } => Some(name.as_str()),
+            ImageStateType::Image { .. } => None,
+        };
+
+        backend.assign(&image, &original, output, collection)?;
+
+        Self::get_hook_context(image_state, output).run_reporting(HookEvent::PostSet);
+
+        Ok(())
+    }
+
+    /// What the hooks get to know about the image, the collection metadata is used if the image is from a collection
+    fn get_hook_context(image_state: &ImageStateType, output: Option<&str>) -> HookContext {
+        let mut context = HookContext {
+            image: Some(image_state.get_image_path().to_owned()),
+            output: output.map(str::to_owned),
+            ..Default::default()
+        };
+
+        if let ImageStateType::Collection { name, image_path } = image_state {
+            context.collection = Some(name.clone());
+
+            let file_name = Path::new(image_path)
+                .file_name()
+                .map(|file_name| file_name.to_string_lossy().into_owned());
+            let metadata = Collection::open(name).ok().and_then(|collection| {
+                collection
+                    .get_directory()
+                    .get_manifest()
+                    .images
+                    .get(&file_name?)
+                    .cloned()
+            });
+
+            if let Some(metadata) = metadata {
+                context.supplier = metadata.supplier;
+                context.source_url = metadata.source_url;
             }
-        } else {
-            Ok(())
         }
+
+        context
+    }
+
+    /// Assigns the images of all outputs, the image for all outputs is assigned first.
+    pub fn assign_current_image(&self) -> Result<(), StateError> {
+        for (output, image_state) in self.get_states() {
+            self.assign_image(image_state, output)?;
+        }
+
+        Ok(())
     }
 
-    pub fn get_state(&self) -> Option<&ImageStateType> {
-        self.image.as_ref()
+    /// Assigns the image of a single output, falls back to the image for all outputs.
+    pub fn assign_output(&self, output: Option<&str>) -> Result<(), StateError> {
+        match self.get_state(output) {
+            Some(image_state) => self.assign_image(image_state, output),
+            None => Ok(()),
+        }
     }
 
-    pub fn get_current_image(&self) -> Result<SavedImage, StateError> {
-        if let Some(current_image) = &self.image {
+    /// Get the state for an output, falls back to the image set for all outputs.
+    pub fn get_state(&self, output: Option<&str>) -> Option<&ImageStateType> {
+        output
+            .and_then(|output| self.outputs.get(output))
+            .or(self.image.as_ref())
+    }
+
+    /// Get all states, the image for all outputs has no output name.
+    pub fn get_states(&self) -> impl Iterator<Item = (Option<&str>, &ImageStateType)> {
+        self.image.iter().map(|image| (None, image)).chain(
+            self.outputs
+                .iter()
+                .map(|(output, image)| (Some(output.as_str()), image)),
+        )
+    }
+
+    pub fn get_current_image(&self, output: Option<&str>) -> Result<SavedImage, StateError> {
+        if let Some(current_image) = self.get_state(output) {
             current_image.load_saved_image()
         } else {
             Err(StateError::NoImageSet)
         }
     }
 
-    /// Sets the current image and assigns it, if there is a command specified, else it just sets the state.
-    pub fn set_current_image(&mut self, image: &SavedImage) -> Result<(), StateError> {
+    /// Stores the image state for the output and records it in the history.
+    pub fn set_state(
+        &mut self,
+        image_state: ImageStateType,
+        output: Option<&str>,
+    ) -> Result<(), StateError> {
+        Self::record_state(&image_state, output)?;
+        self.restore_state(image_state, output);
+
+        Ok(())
+    }
+
+    /// Replaces the image state of the output and records it in the history.
+    /// Unlike setting the image for all outputs, this keeps the per output images.
+    pub fn replace_state(
+        &mut self,
+        image_state: ImageStateType,
+        output: Option<&str>,
+    ) -> Result<(), StateError> {
+        Self::record_state(&image_state, output)?;
+        self.store_state(image_state, output);
+
+        Ok(())
+    }
+
+    /// Runs the pre set hooks, which may veto the image, and records the image in the history.
+    fn record_state(image_state: &ImageStateType, output: Option<&str>) -> Result<(), StateError> {
+        Self::get_hook_context(image_state, output)
+            .run(HookEvent::PreSet)
+            .map_err(StateError::Vetoed)?;
+
+        let mut history = History::open().map_err(StateError::HistoryError)?;
+        history.push(image_state.clone(), output);
+
+        Ok(())
+    }
+
+    /// Stores the image state for the output without recording it in the history.
+    /// Setting the image for all outputs clears the per output images.
+    fn restore_state(&mut self, image_state: ImageStateType, output: Option<&str>) {
+        if output.is_none() {
+            self.outputs.clear();
+        }
+
+        self.store_state(image_state, output);
+    }
+
+    fn store_state(&mut self, image_state: ImageStateType, output: Option<&str>) {
+        match output {
+            Some(output) => {
+                self.outputs.insert(output.to_owned(), image_state);
+            }
+            None => self.image = Some(image_state),
+        }
+    }
+
+    /// Sets the current image of the output, this does not assign it.
+    pub fn set_current_image(
+        &mut self,
+        image: &SavedImage,
+        output: Option<&str>,
+    ) -> Result<(), StateError> {
+        self.set_current_image_with_effects(image, vec![], output)
+    }
+
+    /// Sets the current image of the output, the effects are applied when it is assigned.
+    pub fn set_current_image_with_effects(
+        &mut self,
+        image: &SavedImage,
+        effects: Vec<String>,
+        output: Option<&str>,
+    ) -> Result<(), StateError> {
         match image.get_absolute_path_as_string() {
-            Ok(path) => {
-                self.image = Some(ImageStateType::Image { path });
+            Ok(path) => self.set_state(ImageStateType::Image { path, effects }, output),
+            Err(err) => Err(StateError::ImageError(err)),
+        }
+    }
 
-                Ok(())
+    /// Goes back the amount of steps in the history and assigns that image, returns the new entry.
+    pub fn go_back(&mut self, steps: usize) -> Result<HistoryEntry, StateError> {
+        let mut history = History::open().map_err(StateError::HistoryError)?;
+        let position = history
+            .get_previous_index(steps)
+            .map_err(StateError::HistoryError)?;
+        let entry = history
+            .get_entry(position)
+            .map_err(StateError::HistoryError)?
+            .clone();
+
+        Self::get_hook_context(&entry.image, entry.output.as_deref())
+            .run(HookEvent::PreSet)
+            .map_err(StateError::Vetoed)?;
+
+        self.restore_state(entry.image.clone(), entry.output.as_deref());
+        self.assign_output(entry.output.as_deref())?;
+        history.set_position(position);
+
+        Ok(entry)
+    }
+
+    pub fn get_search_position(&self, search_key: &str) -> SearchPosition {
+        self.search_positions
+            .get(search_key)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub fn set_search_position(&mut self, search_key: String, position: SearchPosition) {
+        self.search_positions.insert(search_key, position);
+    }
+
+    /// Points the states showing the image at its new location, like after it was moved into a collection.
+    /// This is not recorded in the history, as the wallpaper itself doesn't change.
+    pub fn relocate_image(&mut self, from: &str, to: &ImageStateType) {
+        for image_state in self.image.iter_mut().chain(self.outputs.values_mut()) {
+            if image_state.get_image_path() == from {
+                *image_state = to.clone();
             }
-            Err(err) => return Err(StateError::ImageError(err)),
         }
     }
 
@@ -151,17 +384,17 @@ impl State {
         &mut self,
         collection: &Collection,
         image: &SavedImage,
+        output: Option<&str>,
     ) -> Result<(), StateError> {
         match image.get_absolute_path_as_string() {
-            Ok(image_path) => {
-                self.image = Some(ImageStateType::Collection {
+            Ok(image_path) => self.set_state(
+                ImageStateType::Collection {
                     name: collection.get_name().to_owned(),
                     image_path,
-                });
-
-                Ok(())
-            }
-            Err(err) => return Err(StateError::ImageError(err)),
+                },
+                output,
+            ),
+            Err(err) => Err(StateError::ImageError(err)),
         }
     }
 }
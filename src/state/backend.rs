@@ -0,0 +1,327 @@
+use std::{
+    process::{Command, Stdio},
+    thread,
+};
+
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::{
+    config::{GlobalConfig, SetCommandConfig},
+    image::SavedImage,
+    CONFIG,
+};
+
+use super::{
+    command::{run_command, SetImageCommand},
+    StateError,
+};
+
+/// Which program shows the wallpaper
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackendKind {
+    /// Runs the 'set_command' of the config
+    #[default]
+    Custom,
+    Swww,
+    Swaybg,
+    Feh,
+    Hyprpaper,
+    Xwallpaper,
+    Gnome,
+    Kde,
+    /// Copies or links the image to the path of 'file_backend'
+    File,
+}
+
+/// Shows images as the wallpaper
+pub trait Backend {
+    /// Shows the image on the output, or on every output if no output is given.
+    /// The original is the image before it was scaled and had effects applied, for its name and size.
+    fn assign(
+        &self,
+        image: &SavedImage,
+        original: &SavedImage,
+        output: Option<&str>,
+        collection: Option<&str>,
+    ) -> Result<(), StateError>;
+}
+
+fn get_path(image: &SavedImage) -> Result<String, StateError> {
+    image
+        .get_absolute_path_as_string()
+        .map_err(StateError::ImageError)
+}
+
+struct CustomBackend(SetImageCommand);
+
+impl Backend for CustomBackend {
+    fn assign(
+        &self,
+        image: &SavedImage,
+        original: &SavedImage,
+        output: Option<&str>,
+        collection: Option<&str>,
+    ) -> Result<(), StateError> {
+        self.0.apply(image, original, output, collection)
+    }
+}
+
+struct SwwwBackend;
+
+impl Backend for SwwwBackend {
+    fn assign(
+        &self,
+        image: &SavedImage,
+        _: &SavedImage,
+        output: Option<&str>,
+        _: Option<&str>,
+    ) -> Result<(), StateError> {
+        let mut command = Command::new("swww");
+        command.arg("img").arg(get_path(image)?);
+        if let Some(output) = output {
+            command.arg("--outputs").arg(output);
+        }
+
+        run_command(&mut command)
+    }
+}
+
+struct SwaybgBackend;
+
+impl Backend for SwaybgBackend {
+    fn assign(
+        &self,
+        image: &SavedImage,
+        _: &SavedImage,
+        output: Option<&str>,
+        _: Option<&str>,
+    ) -> Result<(), StateError> {
+        // swaybg keeps running to show the wallpaper, so the previous instance for the output is replaced.
+        // The image for all outputs replaces every instance, the instances of other outputs are left running.
+        // Failing to stop it is fine, there might not be one running.
+        let _ = match output {
+            Some(output) => Command::new("pkill")
+                .arg("-f")
+                .arg(format!("^swaybg --output {} ", regex::escape(output)))
+                .output(),
+            None => Command::new("pkill").arg("-x").arg("swaybg").output(),
+        };
+
+        let result = Command::new("swaybg")
+            .arg("--output")
+            .arg(output.unwrap_or("*"))
+            .arg("--image")
+            .arg(get_path(image)?)
+            .arg("--mode")
+            .arg("fill")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        // The daemon keeps running while swaybg is replaced, so the exited instance is waited on to not leave a zombie.
+        // Walltz exiting first is fine, swaybg is then adopted and reaped by init.
+        match result {
+            Ok(mut child) => {
+                thread::spawn(move || child.wait());
+                Ok(())
+            }
+            Err(err) => Err(StateError::FsError(err)),
+        }
+    }
+}
+
+struct FehBackend;
+
+impl Backend for FehBackend {
+    /// feh sets the wallpaper for all screens at once, so the output is ignored
+    fn assign(
+        &self,
+        image: &SavedImage,
+        _: &SavedImage,
+        _: Option<&str>,
+        _: Option<&str>,
+    ) -> Result<(), StateError> {
+        run_command(Command::new("feh").arg("--bg-fill").arg(get_path(image)?))
+    }
+}
+
+struct HyprpaperBackend;
+
+impl Backend for HyprpaperBackend {
+    fn assign(
+        &self,
+        image: &SavedImage,
+        _: &SavedImage,
+        output: Option<&str>,
+        _: Option<&str>,
+    ) -> Result<(), StateError> {
+        let path = get_path(image)?;
+
+        run_command(Command::new("hyprctl").args(["hyprpaper", "preload", &path]))?;
+        // An empty output sets the wallpaper of every monitor
+        run_command(Command::new("hyprctl").args([
+            "hyprpaper",
+            "wallpaper",
+            &format!("{},{}", output.unwrap_or_default(), path),
+        ]))?;
+        // Frees the memory of the previous wallpapers
+        run_command(Command::new("hyprctl").args(["hyprpaper", "unload", "unused"]))
+    }
+}
+
+struct XwallpaperBackend;
+
+impl Backend for XwallpaperBackend {
+    fn assign(
+        &self,
+        image: &SavedImage,
+        _: &SavedImage,
+        output: Option<&str>,
+        _: Option<&str>,
+    ) -> Result<(), StateError> {
+        let mut command = Command::new("xwallpaper");
+        if let Some(output) = output {
+            command.arg("--output").arg(output);
+        }
+        command.arg("--zoom").arg(get_path(image)?);
+
+        run_command(&mut command)
+    }
+}
+
+struct GnomeBackend;
+
+impl Backend for GnomeBackend {
+    /// GNOME has one wallpaper for all monitors, so the output is ignored
+    fn assign(
+        &self,
+        image: &SavedImage,
+        _: &SavedImage,
+        _: Option<&str>,
+        _: Option<&str>,
+    ) -> Result<(), StateError> {
+        let path = image.get_absolute_path().map_err(StateError::ImageError)?;
+        let uri = match Url::from_file_path(&path) {
+            Ok(uri) => uri.to_string(),
+            Err(_) => {
+                return Err(StateError::AssignCommandError(format!(
+                    "No file uri for: {:?}",
+                    path
+                )))
+            }
+        };
+
+        run_command(Command::new("gsettings").args([
+            "set",
+            "org.gnome.desktop.background",
+            "picture-uri",
+            &uri,
+        ]))?;
+
+        // The dark style uses its own wallpaper since GNOME 42, older versions don't have the key
+        let has_dark_key = Command::new("gsettings")
+            .args([
+                "writable",
+                "org.gnome.desktop.background",
+                "picture-uri-dark",
+            ])
+            .output()
+            .is_ok_and(|output| output.status.success() && output.stdout.starts_with(b"true"));
+        if has_dark_key {
+            run_command(Command::new("gsettings").args([
+                "set",
+                "org.gnome.desktop.background",
+                "picture-uri-dark",
+                &uri,
+            ]))?;
+        }
+
+        Ok(())
+    }
+}
+
+struct KdeBackend;
+
+impl Backend for KdeBackend {
+    /// Plasma sets the wallpaper of all desktops, so the output is ignored
+    fn assign(
+        &self,
+        image: &SavedImage,
+        _: &SavedImage,
+        _: Option<&str>,
+        _: Option<&str>,
+    ) -> Result<(), StateError> {
+        run_command(Command::new("plasma-apply-wallpaperimage").arg(get_path(image)?))
+    }
+}
+
+struct FileBackend {
+    /// '{output}' is replaced by the output name, or by 'all' for every output
+    path: String,
+    symlink: bool,
+}
+
+impl Backend for FileBackend {
+    fn assign(
+        &self,
+        image: &SavedImage,
+        _: &SavedImage,
+        output: Option<&str>,
+        _: Option<&str>,
+    ) -> Result<(), StateError> {
+        let goal_path = GlobalConfig::get_config_path()
+            .join(self.path.replace("{output}", output.unwrap_or("all")));
+
+        // Replace the previous wallpaper, which might be a link
+        if goal_path.symlink_metadata().is_ok() {
+            std::fs::remove_file(&goal_path).map_err(StateError::FsError)?;
+        }
+
+        if self.symlink {
+            let source = image.get_absolute_path().map_err(StateError::ImageError)?;
+            std::os::unix::fs::symlink(source, goal_path).map_err(StateError::FsError)
+        } else {
+            std::fs::copy(image.get_path(), goal_path)
+                .map(|_| ())
+                .map_err(StateError::FsError)
+        }
+    }
+}
+
+/// The backend of the config, a collection's own set command always uses the custom backend
+pub fn get_backend(set_command: Option<&SetCommandConfig>) -> Result<Box<dyn Backend>, StateError> {
+    let backend: Box<dyn Backend> = match (set_command, CONFIG.backend) {
+        (Some(set_command), _) => Box::new(CustomBackend(SetImageCommand::new(set_command)?)),
+        (None, BackendKind::Custom) => match &CONFIG.set_command {
+            Some(set_command) => Box::new(CustomBackend(SetImageCommand::new(set_command)?)),
+            None => {
+                return Err(StateError::AssignCommandError(
+                    "Not assign command specified".to_string(),
+                ))
+            }
+        },
+        (None, BackendKind::Swww) => Box::new(SwwwBackend),
+        (None, BackendKind::Swaybg) => Box::new(SwaybgBackend),
+        (None, BackendKind::Feh) => Box::new(FehBackend),
+        (None, BackendKind::Hyprpaper) => Box::new(HyprpaperBackend),
+        (None, BackendKind::Xwallpaper) => Box::new(XwallpaperBackend),
+        (None, BackendKind::Gnome) => Box::new(GnomeBackend),
+        (None, BackendKind::Kde) => Box::new(KdeBackend),
+        (None, BackendKind::File) => match &CONFIG.file_backend.path {
+            Some(path) => Box::new(FileBackend {
+                path: path.clone(),
+                symlink: CONFIG.file_backend.symlink,
+            }),
+            None => {
+                return Err(StateError::AssignCommandError(
+                    "No path specified for the file backend".to_string(),
+                ))
+            }
+        },
+    };
+
+    Ok(backend)
+}
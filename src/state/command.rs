@@ -0,0 +1,351 @@
+use std::process::Command;
+
+use reqwest::Url;
+
+use crate::{config::SetCommandConfig, image::SavedImage};
+
+use super::StateError;
+
+/// Splits a command line into words like a shell does, with single quotes, double quotes and backslash escapes
+fn split_shell_words(line: &str) -> Result<Vec<String>, StateError> {
+    let invalid = |reason: &str| StateError::InvalidCommand(format!("{}: {}", reason, line));
+
+    let mut words = vec![];
+    // None while between words, so quoted empty strings still become a word
+    let mut word: Option<String> = None;
+    let mut characters = line.chars();
+
+    while let Some(character) = characters.next() {
+        match character {
+            ' ' | '\t' | '\n' => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
+            }
+            '\\' => match characters.next() {
+                Some(escaped) => word.get_or_insert_with(String::new).push(escaped),
+                None => return Err(invalid("The command ends in an escape")),
+            },
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match characters.next() {
+                        Some('\'') => break,
+                        Some(character) => word.push(character),
+                        None => return Err(invalid("A single quote is never closed")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match characters.next() {
+                        Some('"') => break,
+                        // Only these characters are escaped within double quotes
+                        Some('\\') => match characters.next() {
+                            Some(escaped @ ('"' | '\\' | '$' | '`')) => word.push(escaped),
+                            Some(character) => {
+                                word.push('\\');
+                                word.push(character);
+                            }
+                            None => return Err(invalid("A double quote is never closed")),
+                        },
+                        Some(character) => word.push(character),
+                        None => return Err(invalid("A double quote is never closed")),
+                    }
+                }
+            }
+            character => word.get_or_insert_with(String::new).push(character),
+        }
+    }
+
+    words.extend(word);
+    Ok(words)
+}
+
+/// The values filled into the placeholders of the set command
+struct CommandValues {
+    path: String,
+    uri: String,
+    name: String,
+    collection: String,
+    width: String,
+    height: String,
+    output: String,
+}
+
+impl CommandValues {
+    /// The path and uri are of the image that is shown, the name and size of the original image.
+    /// The shown image may be a scaled variant with effects, named after its hash.
+    fn new(
+        image: &SavedImage,
+        original: &SavedImage,
+        output: Option<&str>,
+        collection: Option<&str>,
+    ) -> Result<Self, StateError> {
+        let path = image.get_absolute_path().map_err(StateError::ImageError)?;
+        let (width, height) = match image::image_dimensions(original.get_path()) {
+            Ok((width, height)) => (width.to_string(), height.to_string()),
+            Err(_) => Default::default(),
+        };
+
+        Ok(Self {
+            uri: Url::from_file_path(&path)
+                .map(|uri| uri.to_string())
+                .unwrap_or_default(),
+            path: path.to_string_lossy().into_owned(),
+            name: original.get_name().unwrap_or_default(),
+            collection: collection.unwrap_or_default().to_owned(),
+            width,
+            height,
+            output: output.unwrap_or_default().to_owned(),
+        })
+    }
+
+    fn get(&self, placeholder: &str) -> Option<&str> {
+        match placeholder {
+            "path" => Some(&self.path),
+            "uri" => Some(&self.uri),
+            "name" => Some(&self.name),
+            "collection" => Some(&self.collection),
+            "width" => Some(&self.width),
+            "height" => Some(&self.height),
+            "output" => Some(&self.output),
+            _ => None,
+        }
+    }
+
+    /// Fills the placeholders in a single pass, so braces in the values are never filled themselves
+    fn fill(&self, argument: &str) -> String {
+        let mut filled = String::with_capacity(argument.len());
+        let mut rest = argument;
+
+        while let Some(start) = rest.find('{') {
+            filled.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            let value = rest
+                .find('}')
+                .and_then(|end| Some((self.get(&rest[1..end])?, end)));
+
+            match value {
+                Some((value, end)) => {
+                    filled.push_str(value);
+                    rest = &rest[end + 1..];
+                }
+                // Braces that aren't a placeholder are kept as they are
+                None => {
+                    filled.push('{');
+                    rest = &rest[1..];
+                }
+            }
+        }
+
+        filled.push_str(rest);
+        filled
+    }
+}
+
+pub(super) struct SetImageCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl SetImageCommand {
+    pub fn new(command: &SetCommandConfig) -> Result<Self, StateError> {
+        let mut words = match command {
+            SetCommandConfig::Line(line) => split_shell_words(line)?,
+            SetCommandConfig::Args(args) => args.clone(),
+        }
+        .into_iter();
+
+        match words.next() {
+            Some(program) => Ok(Self {
+                program,
+                args: words.collect(),
+            }),
+            None => Err(StateError::InvalidCommand(
+                "The command is empty".to_owned(),
+            )),
+        }
+    }
+
+    /// The filled in arguments. When the image is for every output an argument with '{output}', like
+    /// '--outputs={output}', is left out, together with the flag before a lone '{output}', like '-o {output}'.
+    fn get_args(&self, values: &CommandValues, output: Option<&str>) -> Vec<String> {
+        if output.is_some() {
+            return self
+                .args
+                .iter()
+                .map(|argument| values.fill(argument))
+                .collect();
+        }
+
+        let mut args = self.args.iter().peekable();
+        let mut used_args = vec![];
+        while let Some(argument) = args.next() {
+            let is_output_flag = argument.starts_with('-')
+                && args.peek().is_some_and(|next| next.as_str() == "{output}");
+            if is_output_flag {
+                args.next();
+                continue;
+            }
+
+            if !argument.contains("{output}") {
+                used_args.push(values.fill(argument));
+            }
+        }
+
+        used_args
+    }
+
+    /// Runs the command for the image, other placeholders without a value are replaced by an empty string.
+    pub fn apply(
+        &self,
+        image: &SavedImage,
+        original: &SavedImage,
+        output: Option<&str>,
+        collection: Option<&str>,
+    ) -> Result<(), StateError> {
+        let values = CommandValues::new(image, original, output, collection)?;
+
+        run_command(Command::new(values.fill(&self.program)).args(self.get_args(&values, output)))
+    }
+}
+
+/// Runs the command to completion, failing with its error output if it does not succeed
+pub(super) fn run_command(command: &mut Command) -> Result<(), StateError> {
+    match command.output() {
+        Ok(output) => {
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(StateError::AssignCommandError(
+                    String::from_utf8_lossy(&output.stderr).into_owned(),
+                ))
+            }
+        }
+        Err(err) => Err(StateError::FsError(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_values() -> CommandValues {
+        CommandValues {
+            path: "/images/{name}.png".to_owned(),
+            uri: "file:///images/%7Bname%7D.png".to_owned(),
+            name: "forest".to_owned(),
+            collection: String::new(),
+            width: "1920".to_owned(),
+            height: "1080".to_owned(),
+            output: "DP-1".to_owned(),
+        }
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        let words = split_shell_words("swww  img\t{path}\n").unwrap();
+        assert_eq!(words, ["swww", "img", "{path}"]);
+    }
+
+    #[test]
+    fn keeps_quoted_words_together() {
+        let words = split_shell_words(r#"feh --bg-fill 'my {path}' "a b" '' """#).unwrap();
+        assert_eq!(words, ["feh", "--bg-fill", "my {path}", "a b", "", ""]);
+    }
+
+    #[test]
+    fn joins_adjacent_quotes_into_one_word() {
+        let words = split_shell_words(r#"--image='a b'"c d"e"#).unwrap();
+        assert_eq!(words, ["--image=a bc de"]);
+    }
+
+    #[test]
+    fn handles_escapes() {
+        let words = split_shell_words(r"'d\'").unwrap();
+        assert_eq!(words, [r"d\"], "backslashes are kept within single quotes");
+
+        let words = split_shell_words(r#"a\ b \'c "e\"f\g\\""#).unwrap();
+        assert_eq!(words, ["a b", "'c", r#"e"f\g\"#]);
+    }
+
+    #[test]
+    fn rejects_unterminated_quotes_and_escapes() {
+        assert!(split_shell_words("swww img 'path").is_err());
+        assert!(split_shell_words(r#"swww img "path"#).is_err());
+        assert!(split_shell_words(r#"swww img "path\"#).is_err());
+        assert!(split_shell_words(r"swww img path\").is_err());
+    }
+
+    #[test]
+    fn empty_command_has_no_words() {
+        assert!(split_shell_words("  ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn fills_adjacent_placeholders() {
+        let values = get_values();
+        assert_eq!(values.fill("{width}x{height}"), "1920x1080");
+        assert_eq!(values.fill("{output}{name}"), "DP-1forest");
+    }
+
+    #[test]
+    fn does_not_fill_placeholders_inside_values() {
+        let values = get_values();
+        assert_eq!(values.fill("--image={path}"), "--image=/images/{name}.png");
+    }
+
+    #[test]
+    fn leaves_out_output_arguments_without_an_output() {
+        let command = SetImageCommand::new(&SetCommandConfig::Line(
+            "swww img --outputs={output} {path}".to_owned(),
+        ))
+        .unwrap();
+        let values = get_values();
+
+        assert_eq!(
+            command.get_args(&values, Some("DP-1")),
+            ["img", "--outputs=DP-1", "/images/{name}.png"]
+        );
+        assert_eq!(
+            command.get_args(&values, None),
+            ["img", "/images/{name}.png"]
+        );
+    }
+
+    #[test]
+    fn leaves_out_output_flags_before_a_lone_output() {
+        let command = SetImageCommand::new(&SetCommandConfig::Line(
+            "swww img -o {output} {path} --outputs {output}".to_owned(),
+        ))
+        .unwrap();
+        let values = get_values();
+
+        assert_eq!(
+            command.get_args(&values, Some("DP-1")),
+            [
+                "img",
+                "-o",
+                "DP-1",
+                "/images/{name}.png",
+                "--outputs",
+                "DP-1"
+            ]
+        );
+        assert_eq!(
+            command.get_args(&values, None),
+            ["img", "/images/{name}.png"]
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_placeholders_and_braces() {
+        let values = get_values();
+        assert_eq!(values.fill("{unknown} {name"), "{unknown} {name");
+        assert_eq!(values.fill("{{name}}"), "{forest}");
+        assert_eq!(values.fill("--collection={collection}"), "--collection=");
+    }
+}